@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS SessionTokens;
+ALTER TABLE Players
+    DROP COLUMN PasswordHash,
+    DROP COLUMN Username;
@@ -0,0 +1,13 @@
+ALTER TABLE Players
+    ADD COLUMN Username VARCHAR(32) DEFAULT NULL,
+    ADD COLUMN PasswordHash VARCHAR(255) DEFAULT NULL,
+    ADD UNIQUE KEY (Username);
+
+CREATE TABLE IF NOT EXISTS SessionTokens (
+    TokenHash CHAR(64) NOT NULL PRIMARY KEY,
+    PlayerID INT NOT NULL,
+    ExpiresAt BIGINT NOT NULL,
+
+    FOREIGN KEY (PlayerID) REFERENCES Players (ID),
+    KEY (PlayerID)
+);
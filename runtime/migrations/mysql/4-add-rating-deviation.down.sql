@@ -0,0 +1,3 @@
+ALTER TABLE Players
+    DROP COLUMN RatingVolatility,
+    DROP COLUMN RatingDeviation;
@@ -0,0 +1,3 @@
+ALTER TABLE Players
+    ADD COLUMN RatingDeviation DOUBLE NOT NULL DEFAULT 350,
+    ADD COLUMN RatingVolatility DOUBLE NOT NULL DEFAULT 0.06;
@@ -0,0 +1 @@
+ALTER TABLE Players DROP COLUMN DrawCount;
@@ -0,0 +1 @@
+ALTER TABLE Players ADD COLUMN DrawCount INT NOT NULL DEFAULT 0;
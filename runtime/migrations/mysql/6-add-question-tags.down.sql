@@ -0,0 +1,4 @@
+ALTER TABLE QuestionLists
+    DROP COLUMN Language,
+    DROP COLUMN Difficulty,
+    DROP COLUMN Category;
@@ -0,0 +1,64 @@
+ALTER TABLE QuestionLists
+    ADD COLUMN Category VARCHAR(64) NOT NULL DEFAULT 'general',
+    ADD COLUMN Difficulty INT NOT NULL DEFAULT 2,
+    ADD COLUMN Language VARCHAR(16) NOT NULL DEFAULT 'id';
+
+-- Tags of the questions seeded by 1-create-question.sql
+UPDATE QuestionLists SET Category = 'geography', Difficulty = 1 WHERE Question IN (
+    'Negara Terkecil di Dunia adalah?',
+    'Apa Benua Terbesar di Dunia?',
+    'Jumlah Benua di Dunia',
+    'Negara manakah yang memiliki wilayah terluas di dunia?',
+    'Apa Ibu Kota Jawa Tengah'
+);
+
+UPDATE QuestionLists SET Category = 'geography', Difficulty = 2 WHERE Question IN (
+    'Berapa Jumlah Provinsi di Indonesia?',
+    'Ada berapa negara yang tergabung dalam ASEAN?',
+    'Apa nama mata uang Thailand?',
+    'Washington D.C Ibukota Amerika Serikat Terletak di Benua?'
+);
+
+UPDATE QuestionLists SET Category = 'science', Difficulty = 2 WHERE Question IN (
+    'Berapa Jumlah Tulang Rusuk Manusia?'
+);
+
+UPDATE QuestionLists SET Category = 'science', Difficulty = 3 WHERE Question IN (
+    'Warna yang Paling Panjang dalam Pelangi adalah Warna?'
+);
+
+UPDATE QuestionLists SET Category = 'history', Difficulty = 1 WHERE Question IN (
+    'Siapa pencipta lagu Indonesia Raya?'
+);
+
+UPDATE QuestionLists SET Category = 'history', Difficulty = 2 WHERE Question IN (
+    'Apa nama kerajaan yang pertama kali berdiri di Indonesia?'
+);
+
+UPDATE QuestionLists SET Category = 'history', Difficulty = 3 WHERE Question IN (
+    'Pada tahun berapa Jepang menyerang Pearl Harbor, mengakibatkan Amerika Serikat ikut terlibat dalam Perang Dunia II?'
+);
+
+UPDATE QuestionLists SET Category = 'art', Difficulty = 1 WHERE Question IN (
+    'Apa saja Warna Primer?'
+);
+
+UPDATE QuestionLists SET Category = 'art', Difficulty = 2 WHERE Question IN (
+    '“Starry Night” adalah Karya Lukisan dari?'
+);
+
+UPDATE QuestionLists SET Category = 'literature', Difficulty = 1 WHERE Question IN (
+    'Harry Potter adalah Novel yang Ditulis Oleh?'
+);
+
+UPDATE QuestionLists SET Category = 'math', Difficulty = 1 WHERE Question IN (
+    '5 + 3 * 4'
+);
+
+UPDATE QuestionLists SET Category = 'math', Difficulty = 2 WHERE Question IN (
+    'Jika 2x + 1 = 5, berapakah nilai dari x ?'
+);
+
+UPDATE QuestionLists SET Category = 'math', Difficulty = 3 WHERE Question IN (
+    'Jika x + y = 9 dan x - y = -5, berapakah nilai dari 2x + y ?'
+);
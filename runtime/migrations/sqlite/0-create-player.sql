@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS Players (
+    ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    RID VARCHAR(32) NOT NULL,
+    Name VARCHAR(100) NOT NULL DEFAULT '',
+    Rating INTEGER NOT NULL DEFAULT 0,
+    WinCount INTEGER NOT NULL DEFAULT 0,
+    LoseCount INTEGER NOT NULL DEFAULT 0,
+    ProfilePicture BLOB DEFAULT NULL,
+    ProfilePictureExtension VARCHAR(10) DEFAULT '.png'
+);
+
+CREATE INDEX IF NOT EXISTS PlayersRID ON Players (RID);
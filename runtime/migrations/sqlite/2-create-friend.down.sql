@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS FriendRequests;
+DROP TABLE IF EXISTS FriendLists;
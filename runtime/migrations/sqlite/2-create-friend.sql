@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS FriendLists (
+    PlayerID INTEGER NOT NULL,
+    FriendID INTEGER NOT NULL,
+
+    FOREIGN KEY (PlayerID) REFERENCES Players (ID),
+    FOREIGN KEY (FriendID) REFERENCES Players (ID),
+    PRIMARY KEY (PlayerID, FriendID)
+);
+
+CREATE TABLE IF NOT EXISTS FriendRequests (
+    PlayerID INTEGER NOT NULL,
+    FriendID INTEGER NOT NULL,
+    DateRequested DATE NOT NULL,
+
+    FOREIGN KEY (PlayerID) REFERENCES Players (ID),
+    FOREIGN KEY (FriendID) REFERENCES Players (ID),
+    PRIMARY KEY (PlayerID, FriendID)
+);
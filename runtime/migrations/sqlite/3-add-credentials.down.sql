@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS SessionTokens;
+DROP INDEX IF EXISTS PlayersUsername;
+ALTER TABLE Players DROP COLUMN PasswordHash;
+ALTER TABLE Players DROP COLUMN Username;
@@ -0,0 +1,14 @@
+ALTER TABLE Players ADD COLUMN Username VARCHAR(32) DEFAULT NULL;
+ALTER TABLE Players ADD COLUMN PasswordHash VARCHAR(255) DEFAULT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS PlayersUsername ON Players (Username);
+
+CREATE TABLE IF NOT EXISTS SessionTokens (
+    TokenHash CHAR(64) NOT NULL PRIMARY KEY,
+    PlayerID INTEGER NOT NULL,
+    ExpiresAt BIGINT NOT NULL,
+
+    FOREIGN KEY (PlayerID) REFERENCES Players (ID)
+);
+
+CREATE INDEX IF NOT EXISTS SessionTokensPlayerID ON SessionTokens (PlayerID);
@@ -0,0 +1,2 @@
+ALTER TABLE Players DROP COLUMN RatingVolatility;
+ALTER TABLE Players DROP COLUMN RatingDeviation;
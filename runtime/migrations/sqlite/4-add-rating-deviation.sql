@@ -0,0 +1,2 @@
+ALTER TABLE Players ADD COLUMN RatingDeviation REAL NOT NULL DEFAULT 350;
+ALTER TABLE Players ADD COLUMN RatingVolatility REAL NOT NULL DEFAULT 0.06;
@@ -0,0 +1 @@
+ALTER TABLE Players ADD COLUMN DrawCount INTEGER NOT NULL DEFAULT 0;
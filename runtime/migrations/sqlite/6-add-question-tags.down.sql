@@ -0,0 +1,3 @@
+ALTER TABLE QuestionLists DROP COLUMN Language;
+ALTER TABLE QuestionLists DROP COLUMN Difficulty;
+ALTER TABLE QuestionLists DROP COLUMN Category;
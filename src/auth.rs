@@ -0,0 +1,434 @@
+use crate::database::{PlayerRecord, SessionToken, Storage};
+use anyhow::{anyhow, Context};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const USERNAME_MAX_LENGTH: usize = 32;
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+
+/// How a client proves who it is in the `Authentication` packet.
+#[derive(Debug)]
+pub enum Credentials {
+    /// First-time players and legacy clients. Only accepted for accounts
+    /// without a username, otherwise knowing the RID would be enough to take
+    /// the account over.
+    Guest {
+        rid: String,
+    },
+    Password {
+        username: String,
+        password: String,
+    },
+    /// A token issued by an earlier authentication
+    Token {
+        token: String,
+    },
+}
+
+impl Credentials {
+    /// Logins with the same credentials run in order on the database
+    /// workers, so the same RID never registers two guests.
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Guest { rid } => rid,
+            Self::Password { username, .. } => username,
+            Self::Token { token } => token,
+        }
+    }
+}
+
+/// The credentials were wrong, as opposed to the database failing. The
+/// message is safe to show to the client.
+#[derive(Debug)]
+pub struct InvalidCredentials(pub &'static str);
+
+impl fmt::Display for InvalidCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCredentials {}
+
+/// A session token as handed to the client. Only `hash` is stored.
+#[derive(Debug, Clone)]
+pub struct IssuedToken {
+    pub token: String,
+    pub hash: String,
+    pub expires_at: i64,
+}
+
+pub struct Session {
+    pub record: PlayerRecord,
+    pub token: IssuedToken,
+}
+
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() as i64)
+}
+
+pub fn unix_time_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_millis() as i64)
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Argon2id with a random salt, encoded as a PHC string.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("Cannot hash password: {}", e))?;
+
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+/// Checks a username and password before they are registered.
+pub fn validate_credentials(
+    username: &str,
+    password: &str,
+    min_password_length: usize,
+) -> Result<(), &'static str> {
+    if username.is_empty() || username.len() > USERNAME_MAX_LENGTH {
+        return Err("Username must be 1 to 32 characters long");
+    }
+
+    if !username
+        .chars()
+        .all(|x| x.is_ascii_alphanumeric() || x == '_')
+    {
+        return Err("Username may only contain letters, digits and underscores");
+    }
+
+    if password.len() < min_password_length {
+        return Err("Password is too short");
+    }
+
+    if password.len() > PASSWORD_MAX_LENGTH {
+        return Err("Password is too long");
+    }
+
+    Ok(())
+}
+
+/// Creates and stores a new token for `player_id`. Expired tokens of the
+/// player are removed on the way, clients that never present them again
+/// would otherwise keep them forever.
+pub fn issue_token(
+    db: &mut dyn Storage,
+    player_id: i32,
+    ttl_secs: u64,
+) -> anyhow::Result<IssuedToken> {
+    db.remove_expired_session_tokens(player_id, unix_time())
+        .context("remove_expired_session_tokens fails")?;
+
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let issued = IssuedToken {
+        hash: hash_token(&token),
+        expires_at: unix_time() + ttl_secs as i64,
+        token,
+    };
+
+    db.insert_session_token(&SessionToken {
+        player_id,
+        token_hash: issued.hash.clone(),
+        expires_at: issued.expires_at,
+    })
+    .context("insert_session_token fails")?;
+
+    Ok(issued)
+}
+
+/// Runs on a database worker. Fails with `InvalidCredentials` if the client
+/// could not prove it owns the account.
+pub fn authenticate(
+    db: &mut dyn Storage,
+    credentials: Credentials,
+    ttl_secs: u64,
+) -> anyhow::Result<Session> {
+    match credentials {
+        Credentials::Guest { rid } => {
+            let record = db.load_or_insert_player(&rid)?;
+            if record.username.is_some() {
+                return Err(InvalidCredentials("This account needs a password or token").into());
+            }
+
+            let token = issue_token(db, record.id, ttl_secs)?;
+            Ok(Session { record, token })
+        }
+
+        Credentials::Password { username, password } => {
+            let credentials = db
+                .load_credentials(&username)
+                .context("load_credentials fails")?;
+
+            let Some((id, hash)) = credentials else {
+                return Err(InvalidCredentials("Wrong username or password").into());
+            };
+
+            if !verify_password(&password, &hash) {
+                return Err(InvalidCredentials("Wrong username or password").into());
+            }
+
+            let record = db
+                .load_player_by_id(id)
+                .context("load_player_by_id fails")?;
+            let token = issue_token(db, id, ttl_secs)?;
+            Ok(Session { record, token })
+        }
+
+        Credentials::Token { token } => {
+            let hash = hash_token(&token);
+            let stored = db
+                .load_session_token(&hash)
+                .context("load_session_token fails")?;
+
+            let Some(stored) = stored else {
+                return Err(InvalidCredentials("Invalid or revoked token").into());
+            };
+
+            if stored.expires_at <= unix_time() {
+                db.remove_session_token(&hash)
+                    .context("remove_session_token fails")?;
+                return Err(InvalidCredentials("Token expired").into());
+            }
+
+            let record = db
+                .load_player_by_id(stored.player_id)
+                .context("load_player_by_id fails")?;
+
+            Ok(Session {
+                record,
+                token: IssuedToken {
+                    token,
+                    hash,
+                    expires_at: stored.expires_at,
+                },
+            })
+        }
+    }
+}
+
+/// Replaces the token `old_hash` with a fresh one.
+pub fn refresh_token(
+    db: &mut dyn Storage,
+    player_id: i32,
+    old_hash: Option<&str>,
+    ttl_secs: u64,
+) -> anyhow::Result<IssuedToken> {
+    let token = issue_token(db, player_id, ttl_secs)?;
+
+    if let Some(old_hash) = old_hash {
+        db.remove_session_token(old_hash)
+            .context("remove_session_token fails")?;
+    }
+
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, database::memory::MemoryDatabase};
+
+    fn database() -> MemoryDatabase {
+        MemoryDatabase::new(&Config::default()).unwrap()
+    }
+
+    fn is_invalid_credentials(result: anyhow::Result<Session>) -> bool {
+        result.is_err_and(|e| e.is::<InvalidCredentials>())
+    }
+
+    fn guest(rid: &str) -> Credentials {
+        Credentials::Guest {
+            rid: rid.to_string(),
+        }
+    }
+
+    fn token(token: &str) -> Credentials {
+        Credentials::Token {
+            token: token.to_string(),
+        }
+    }
+
+    #[test]
+    fn credentials_and_tokens() {
+        let mut db = database();
+        let session = authenticate(&mut db, guest("rid"), 60).unwrap();
+        let id = session.record.id;
+
+        let hash = hash_password("hunter22").unwrap();
+        assert!(!db.try_save_credentials(id, "alice", &hash).unwrap());
+        assert_eq!(
+            db.load_player_by_id(id).unwrap().username.as_deref(),
+            Some("alice")
+        );
+
+        // The username is taken for everyone else
+        let other = db.load_or_insert_player("other").unwrap().id;
+        assert!(db.try_save_credentials(other, "alice", &hash).unwrap());
+
+        // Knowing the RID is not enough anymore
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            guest("rid"),
+            60
+        )));
+
+        let password = |password: &str| Credentials::Password {
+            username: "alice".to_string(),
+            password: password.to_string(),
+        };
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            password("wrong"),
+            60
+        )));
+        let session = authenticate(&mut db, password("hunter22"), 60).unwrap();
+        assert_eq!(session.record.id, id);
+
+        let session = authenticate(&mut db, token(&session.token.token), 60).unwrap();
+        assert_eq!(session.record.id, id);
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            token("bogus"),
+            60
+        )));
+
+        // A refreshed token replaces the old one
+        let refreshed = refresh_token(&mut db, id, Some(&session.token.hash), 60).unwrap();
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            token(&session.token.token),
+            60
+        )));
+
+        // Both the first guest token and the refreshed one are revoked
+        assert_eq!(db.remove_session_tokens(id).unwrap(), 2);
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            token(&refreshed.token),
+            60
+        )));
+    }
+
+    #[test]
+    fn expired_token_is_removed() {
+        let mut db = database();
+        let session = authenticate(&mut db, guest("rid"), 0).unwrap();
+
+        let result = authenticate(&mut db, token(&session.token.token), 0);
+        assert!(is_invalid_credentials(result));
+        assert!(db
+            .load_session_token(&session.token.hash)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let mut db = database();
+        let id = db.load_or_insert_player("rid").unwrap().id;
+        let hash = hash_password("hunter22").unwrap();
+        db.save_credentials(id, "alice", &hash).unwrap();
+
+        let login = |username: &str, password: &str| Credentials::Password {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            login("alice", "hunter23"),
+            60
+        )));
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            login("alice", ""),
+            60
+        )));
+        assert!(is_invalid_credentials(authenticate(
+            &mut db,
+            login("bob", "hunter22"),
+            60
+        )));
+        assert!(authenticate(&mut db, login("alice", "hunter22"), 60).is_ok());
+    }
+
+    #[test]
+    fn revoking_all_tokens_ends_every_session() {
+        let mut db = database();
+        let first = authenticate(&mut db, guest("rid"), 60).unwrap();
+        let second = authenticate(&mut db, guest("rid"), 60).unwrap();
+        let other = authenticate(&mut db, guest("other"), 60).unwrap();
+
+        assert_eq!(db.remove_session_tokens(first.record.id).unwrap(), 2);
+
+        for session in [first, second] {
+            let result = authenticate(&mut db, token(&session.token.token), 60);
+            assert!(is_invalid_credentials(result));
+        }
+
+        // Other accounts keep their tokens
+        assert!(authenticate(&mut db, token(&other.token.token), 60).is_ok());
+    }
+
+    #[test]
+    fn credentials_are_validated() {
+        assert!(validate_credentials("alice", "12345678", 8).is_ok());
+        assert!(validate_credentials("alice_2", "12345678", 8).is_ok());
+
+        assert!(validate_credentials("alice", "1234567", 8).is_err());
+        assert!(validate_credentials("alice", &"x".repeat(PASSWORD_MAX_LENGTH + 1), 8).is_err());
+        assert!(validate_credentials("", "12345678", 8).is_err());
+        assert!(validate_credentials(&"a".repeat(USERNAME_MAX_LENGTH + 1), "12345678", 8).is_err());
+        assert!(validate_credentials("alice smith", "12345678", 8).is_err());
+    }
+
+    #[test]
+    fn expired_tokens_are_removed_on_login() {
+        let mut db = database();
+        let expired = authenticate(&mut db, guest("rid"), 0).unwrap();
+        let other = authenticate(&mut db, guest("other"), 0).unwrap();
+
+        let live = authenticate(&mut db, guest("rid"), 60).unwrap();
+        assert!(db
+            .load_session_token(&expired.token.hash)
+            .unwrap()
+            .is_none());
+        assert!(db.load_session_token(&live.token.hash).unwrap().is_some());
+
+        // Only the tokens of the player logging in
+        assert!(db.load_session_token(&other.token.hash).unwrap().is_some());
+    }
+}
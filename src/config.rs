@@ -0,0 +1,541 @@
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Si Paling Cerdas game server")]
+pub struct Cli {
+    /// Path to the TOML configuration file
+    #[arg(short, long, env = "SPC_CONFIG", default_value = "config.toml")]
+    pub config: PathBuf,
+
+    #[arg(long, env = "SPC_PORT")]
+    pub port: Option<u16>,
+
+    #[arg(long, env = "SPC_MAX_PEERS")]
+    pub max_peers: Option<usize>,
+
+    #[arg(long, env = "SPC_RUNTIME_DIR")]
+    pub runtime_dir: Option<PathBuf>,
+
+    #[arg(long, env = "SPC_DB_BACKEND")]
+    pub db_backend: Option<DatabaseBackend>,
+
+    #[arg(long, env = "SPC_DB_PATH")]
+    pub db_path: Option<PathBuf>,
+
+    #[arg(long, env = "SPC_DB_HOST")]
+    pub db_host: Option<String>,
+
+    #[arg(long, env = "SPC_DB_PORT")]
+    pub db_port: Option<u16>,
+
+    #[arg(long, env = "SPC_DB_USER")]
+    pub db_user: Option<String>,
+
+    #[arg(long, env = "SPC_DB_PASS")]
+    pub db_pass: Option<String>,
+
+    #[arg(long, env = "SPC_DB_NAME")]
+    pub db_name: Option<String>,
+
+    #[arg(long, env = "SPC_DB_WORKERS")]
+    pub db_workers: Option<usize>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage the database schema instead of starting the server
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Import or export the question bank instead of starting the server
+    Questions {
+        #[command(subcommand)]
+        action: QuestionAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MigrateAction {
+    /// Apply every pending migration
+    Up,
+    /// Revert the most recently applied migrations
+    Down {
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// List migrations and whether they have been applied
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum QuestionAction {
+    /// Add the questions of a file, nothing is added if any of them is invalid
+    Import {
+        path: PathBuf,
+        /// Guessed from the file extension if missing
+        #[arg(long)]
+        format: Option<QuestionFormat>,
+        /// Only validate the file and report what would be imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write every question to a file
+    Export {
+        path: PathBuf,
+        /// Guessed from the file extension if missing
+        #[arg(long)]
+        format: Option<QuestionFormat>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuestionFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub game: GameConfig,
+    pub rules: RulesConfig,
+    pub matchmaking: MatchmakingConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub max_peers: usize,
+    pub runtime_dir: PathBuf,
+}
+
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    #[default]
+    Mysql,
+    /// Single file database, requires the `sqlite` feature
+    Sqlite,
+    /// Non-persistent storage, for running the server without MySQL
+    Memory,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub backend: DatabaseBackend,
+    /// Database file, only used by the SQLite backend
+    pub path: PathBuf,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub pass: String,
+    pub name: String,
+    pub connect_timeout_secs: u64,
+    /// Number of threads running queries, also the MySQL pool size
+    pub workers: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    /// How long the slot of a player who dropped out of a match is held for
+    /// them to reconnect
+    pub reconnect_grace_secs: u64,
+    /// How often the question bank is reloaded from the database, 0 to only
+    /// load it on startup
+    pub question_reload_secs: u64,
+}
+
+/// Picked by the client when queueing, each length has its own queue and
+/// `MatchRules`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchLength {
+    Quick,
+    #[default]
+    Standard,
+    Marathon,
+}
+
+/// How answers are turned into points.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scoring {
+    /// `max_points` for an instant answer, down to 0 when the question closes
+    Linear,
+    /// `max_points` for every correct answer
+    Flat,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MatchRules {
+    pub rounds: i32,
+    /// Delay between the match being found and the first question
+    pub countdown_secs: u64,
+    /// How long a question takes answers, it closes early once everyone
+    /// answered
+    pub question_secs: u64,
+    /// How long the correct answer is shown
+    pub reveal_secs: u64,
+    /// Pause between the reveal and the next question
+    pub intermission_secs: u64,
+    pub scoring: Scoring,
+    pub max_points: i32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    pub quick: MatchRules,
+    pub standard: MatchRules,
+    pub marathon: MatchRules,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchmakingConfig {
+    /// Largest rating difference accepted right after queueing
+    pub initial_rating_window: i32,
+    /// How much the window widens for every second spent in the queue
+    pub rating_window_growth_per_sec: i32,
+    pub max_rating_window: i32,
+    /// Queued players are sent their position and wait time this often
+    pub status_interval_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Lifetime of the session tokens handed out on authentication
+    pub token_ttl_secs: u64,
+    pub min_password_length: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 17091,
+            max_peers: 1024,
+            runtime_dir: PathBuf::from("runtime"),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            backend: DatabaseBackend::Mysql,
+            path: PathBuf::from("si_paling_cerdas.db"),
+            host: String::from("localhost"),
+            port: 3306,
+            user: String::from("root"),
+            pass: String::from("root"),
+            name: String::from("si_paling_cerdas"),
+            connect_timeout_secs: 3,
+            workers: 4,
+        }
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            reconnect_grace_secs: 30,
+            question_reload_secs: 300,
+        }
+    }
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self {
+            quick: MatchRules {
+                rounds: 2,
+                countdown_secs: 2,
+                question_secs: 6,
+                reveal_secs: 2,
+                intermission_secs: 1,
+                scoring: Scoring::Linear,
+                max_points: 15,
+            },
+            standard: MatchRules {
+                rounds: 2,
+                countdown_secs: 3,
+                question_secs: 12,
+                reveal_secs: 3,
+                intermission_secs: 2,
+                scoring: Scoring::Linear,
+                max_points: 15,
+            },
+            marathon: MatchRules {
+                rounds: 10,
+                countdown_secs: 5,
+                question_secs: 17,
+                reveal_secs: 3,
+                intermission_secs: 2,
+                scoring: Scoring::Flat,
+                max_points: 10,
+            },
+        }
+    }
+}
+
+impl RulesConfig {
+    pub fn get(&self, length: MatchLength) -> &MatchRules {
+        match length {
+            MatchLength::Quick => &self.quick,
+            MatchLength::Standard => &self.standard,
+            MatchLength::Marathon => &self.marathon,
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, rules) in [
+            ("quick", &self.quick),
+            ("standard", &self.standard),
+            ("marathon", &self.marathon),
+        ] {
+            rules
+                .validate()
+                .with_context(|| format!("Invalid rules.{}", name))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl MatchRules {
+    /// A match needs at least one question that is open long enough to
+    /// score anything.
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.rounds < 1 {
+            bail!("rounds must be at least 1, got {}", self.rounds);
+        }
+
+        if self.question_secs < 1 {
+            bail!("question_secs must be at least 1");
+        }
+
+        if self.max_points < 1 {
+            bail!("max_points must be at least 1, got {}", self.max_points);
+        }
+
+        Ok(())
+    }
+
+    /// Points for a correct answer given `elapsed_secs` after the question.
+    pub fn points(&self, elapsed_secs: u64) -> i32 {
+        match self.scoring {
+            Scoring::Flat => self.max_points,
+            Scoring::Linear => {
+                let remaining = self.question_secs.saturating_sub(elapsed_secs);
+                (self.max_points as u64 * remaining / self.question_secs.max(1)) as i32
+            }
+        }
+    }
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        Self {
+            initial_rating_window: 100,
+            rating_window_growth_per_sec: 10,
+            max_rating_window: 1000,
+            status_interval_secs: 5,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            token_ttl_secs: 30 * 24 * 60 * 60,
+            min_password_length: 8,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file (if present) and applies the environment
+    /// variable and command line overrides on top of it.
+    pub fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let mut config = Self::from_file(&cli.config)?;
+        config.apply_cli(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.database.workers < 1 {
+            bail!("database.workers must be at least 1");
+        }
+
+        self.rules.validate()
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            println!("Config file {} not found, using defaults", path.display());
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config file {}", path.display()))?;
+
+        toml::from_str(&content)
+            .with_context(|| format!("Cannot parse config file {}", path.display()))
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+
+        if let Some(max_peers) = cli.max_peers {
+            self.server.max_peers = max_peers;
+        }
+
+        if let Some(runtime_dir) = &cli.runtime_dir {
+            self.server.runtime_dir = runtime_dir.clone();
+        }
+
+        if let Some(backend) = cli.db_backend {
+            self.database.backend = backend;
+        }
+
+        if let Some(path) = &cli.db_path {
+            self.database.path = path.clone();
+        }
+
+        if let Some(host) = &cli.db_host {
+            self.database.host = host.clone();
+        }
+
+        if let Some(port) = cli.db_port {
+            self.database.port = port;
+        }
+
+        if let Some(user) = &cli.db_user {
+            self.database.user = user.clone();
+        }
+
+        if let Some(pass) = &cli.db_pass {
+            self.database.pass = pass.clone();
+        }
+
+        if let Some(name) = &cli.db_name {
+            self.database.name = name.clone();
+        }
+
+        if let Some(workers) = cli.db_workers {
+            self.database.workers = workers;
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn migrations_dir(&self, backend: DatabaseBackend) -> PathBuf {
+        let dialect = match backend {
+            DatabaseBackend::Mysql => "mysql",
+            DatabaseBackend::Sqlite => "sqlite",
+            DatabaseBackend::Memory => "memory",
+        };
+
+        self.runtime_dir.join("migrations").join(dialect)
+    }
+
+    pub fn empty_profile_picture(&self) -> PathBuf {
+        self.runtime_dir.join("EmptyProfilePicture.png")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_quick_rules(change: impl FnOnce(&mut MatchRules)) -> RulesConfig {
+        let mut rules = RulesConfig::default();
+        change(&mut rules.quick);
+        rules
+    }
+
+    #[test]
+    fn default_rules_are_valid() {
+        RulesConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn example_config_is_the_default() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("config.example.toml");
+        assert!(path.exists());
+        let config = Config::from_file(&path).unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.database.workers, Config::default().database.workers);
+        assert_eq!(
+            config.rules.marathon.rounds,
+            RulesConfig::default().marathon.rounds
+        );
+    }
+
+    #[test]
+    fn rules_table_has_to_be_complete() {
+        let error = toml::from_str::<Config>("[rules.quick]\nrounds = 3\n").unwrap_err();
+        assert!(error.to_string().contains("missing field"));
+
+        // Left out entirely, the defaults apply
+        let config = toml::from_str::<Config>("[server]\nport = 1\n").unwrap();
+        assert_eq!(
+            config.rules.quick.rounds,
+            RulesConfig::default().quick.rounds
+        );
+    }
+
+    #[test]
+    fn zero_workers_are_rejected() {
+        let mut config = Config::default();
+        config.validate().unwrap();
+
+        config.database.workers = 0;
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("database.workers"));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let invalid = [
+            with_quick_rules(|x| x.rounds = 0),
+            with_quick_rules(|x| x.rounds = -3),
+            with_quick_rules(|x| x.question_secs = 0),
+            with_quick_rules(|x| x.max_points = 0),
+        ];
+
+        for rules in invalid {
+            let error = rules.validate().unwrap_err();
+            assert!(error.to_string().contains("rules.quick"));
+        }
+    }
+
+    #[test]
+    fn linear_points_run_down_to_zero() {
+        let rules = RulesConfig::default().quick;
+
+        assert_eq!(rules.points(0), rules.max_points);
+        assert!(rules.points(rules.question_secs / 2) < rules.max_points);
+        assert_eq!(rules.points(rules.question_secs), 0);
+    }
+}
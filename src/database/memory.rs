@@ -0,0 +1,521 @@
+use super::{
+    FriendInfo, FriendRequestInfo, LeaderboardInfo, PlayerRank, PlayerRecord, SessionToken, Storage,
+};
+use crate::{config::Config, rating, state::GameQuestion};
+use anyhow::anyhow;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+/// Same questions as `1-create-question.sql`, with the category and
+/// difficulty of `6-add-question-tags.sql`, so an offline server has
+/// something to play with.
+const SEED_QUESTIONS: &[(&str, [&str; 4], i32, &str, i32)] = &[
+    ("Berapa Jumlah Provinsi di Indonesia?", ["36", "37", "38", "39"], 2, "geography", 2),
+    ("Negara Terkecil di Dunia adalah?", ["Vatikan", "Chili", "Bolivia", "Hawai"], 0, "geography", 1),
+    ("Berapa Jumlah Tulang Rusuk Manusia?", ["24", "22", "26", "20"], 0, "science", 2),
+    (
+        "Warna yang Paling Panjang dalam Pelangi adalah Warna?",
+        ["Merah", "Nila", "Ungu", "Kuning"],
+        0,
+        "science",
+        3,
+    ),
+    (
+        "Siapa pencipta lagu Indonesia Raya?",
+        ["R.Kusbini", "Ismail Marzuki", "W.R Supratman", "Ibu Soed"],
+        2,
+        "history",
+        1,
+    ),
+    (
+        "Apa saja Warna Primer?",
+        ["Biru, Merah, Hijau", "Merah, Kuning, Biru", "Kuning, Biru, Hitam", "Putih, Hijau, Hitam"],
+        1,
+        "art",
+        1,
+    ),
+    (
+        "Apa nama kerajaan yang pertama kali berdiri di Indonesia?",
+        ["Kutai", "Sriwijaya", "Majapahit", "Singasari"],
+        0,
+        "history",
+        2,
+    ),
+    (
+        "“Starry Night” adalah Karya Lukisan dari?",
+        ["Leonardo da Vinci", "Pablo Picasso", "Claude Monet", "Vincent van Gogh"],
+        3,
+        "art",
+        2,
+    ),
+    (
+        "Apa Benua Terbesar di Dunia?",
+        ["Benua Eropa", "Benua Asia", "Benua Amerika", "Benua Antartika"],
+        1,
+        "geography",
+        1,
+    ),
+    ("Jumlah Benua di Dunia", ["6", "5", "8", "7"], 3, "geography", 1),
+    (
+        "Negara manakah yang memiliki wilayah terluas di dunia?",
+        ["China", "Rusia", "Amerika Serikat", "Vietnam"],
+        1,
+        "geography",
+        1,
+    ),
+    (
+        "Harry Potter adalah Novel yang Ditulis Oleh?",
+        ["George Orwell", "Raditya Dika", "J.K Rowling", "Rick Riordan"],
+        2,
+        "literature",
+        1,
+    ),
+    ("Ada berapa negara yang tergabung dalam ASEAN?", ["9", "12", "10", "11"], 3, "geography", 2),
+    ("Apa Ibu Kota Jawa Tengah", ["Surakarta", "Solo", "Semarang", "Surabaya"], 2, "geography", 1),
+    ("Apa nama mata uang Thailand?", ["Rupiah", "Bath", "Euro", "Dolar"], 1, "geography", 2),
+    ("5 + 3 * 4", ["32", "17", "18", "33"], 1, "math", 1),
+    (
+        "Pada tahun berapa Jepang menyerang Pearl Harbor, mengakibatkan Amerika Serikat ikut terlibat dalam Perang Dunia II?",
+        ["1941", "1942", "1943", "1944"],
+        0,
+        "history",
+        3,
+    ),
+    ("Jika 2x + 1 = 5, berapakah nilai dari x ?", ["1", "2", "3", "4"], 1, "math", 2),
+    (
+        "Jika x + y = 9 dan x - y = -5, berapakah nilai dari 2x + y ?",
+        ["9", "10", "11", "20"],
+        2,
+        "math",
+        3,
+    ),
+    (
+        "Washington D.C Ibukota Amerika Serikat Terletak di Benua?",
+        ["Amerika Timur", "Amerika Barat", "Amerika Selatan", "Amerika Utara"],
+        3,
+        "geography",
+        2,
+    ),
+];
+
+/// Non-persistent storage, everything is lost when the server stops.
+pub struct MemoryDatabase {
+    pub players: HashMap<i32, PlayerRecord>,
+    pub questions: Vec<GameQuestion>,
+    /// (PlayerID, FriendID) pairs, mirroring the `FriendLists` table
+    pub friends: BTreeSet<(i32, i32)>,
+    /// (PlayerID, FriendID) pairs, mirroring the `FriendRequests` table
+    pub friend_requests: BTreeSet<(i32, i32)>,
+    /// Password hashes by player ID
+    pub password_hashes: HashMap<i32, String>,
+    /// Mirrors the `SessionTokens` table, keyed by token hash
+    pub session_tokens: HashMap<String, SessionToken>,
+    pub next_player_id: i32,
+    pub empty_pfp_path: PathBuf,
+}
+
+impl MemoryDatabase {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let questions = SEED_QUESTIONS
+            .iter()
+            .zip(1..)
+            .map(
+                |((question, options, answer_index, category, difficulty), id)| GameQuestion {
+                    id,
+                    question: question.to_string(),
+                    answer_option_1: options[0].to_string(),
+                    answer_option_2: options[1].to_string(),
+                    answer_option_3: options[2].to_string(),
+                    answer_option_4: options[3].to_string(),
+                    answer_index: *answer_index,
+                    category: category.to_string(),
+                    difficulty: *difficulty,
+                    language: String::from("id"),
+                },
+            )
+            .collect();
+
+        Ok(Self {
+            questions,
+            players: HashMap::new(),
+            friends: BTreeSet::new(),
+            friend_requests: BTreeSet::new(),
+            password_hashes: HashMap::new(),
+            session_tokens: HashMap::new(),
+            next_player_id: 1,
+            empty_pfp_path: config.server.empty_profile_picture(),
+        })
+    }
+
+    fn player(&self, id: i32) -> anyhow::Result<&PlayerRecord> {
+        self.players
+            .get(&id)
+            .ok_or_else(|| anyhow!("Cannot find player with ID of {}", id))
+    }
+
+    fn player_mut(&mut self, id: i32) -> anyhow::Result<&mut PlayerRecord> {
+        self.players
+            .get_mut(&id)
+            .ok_or_else(|| anyhow!("Cannot find player with ID of {}", id))
+    }
+
+    fn player_by_name(&self, name: &str) -> Option<&PlayerRecord> {
+        self.players.values().find(|player| player.name == name)
+    }
+
+    /// Emulates the foreign keys and primary key of the friend tables.
+    fn check_pair(&self, table: &BTreeSet<(i32, i32)>, id: i32, target: i32) -> anyhow::Result<()> {
+        self.player(id)?;
+        self.player(target)?;
+
+        if table.contains(&(id, target)) {
+            return Err(anyhow!("Duplicate entry ({}, {})", id, target));
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for MemoryDatabase {
+    fn is_player_exist_by_rid(&mut self, rid: &str) -> anyhow::Result<bool> {
+        Ok(self.players.values().any(|player| player.rid == rid))
+    }
+
+    fn is_player_exist_by_name(&mut self, name: &str) -> anyhow::Result<bool> {
+        Ok(self.player_by_name(name).is_some())
+    }
+
+    fn insert_new_player(&mut self, rid: &str) -> anyhow::Result<()> {
+        let id = self.next_player_id;
+        let pfp_blob = std::fs::read(&self.empty_pfp_path)?;
+
+        self.next_player_id += 1;
+        self.players.insert(
+            id,
+            PlayerRecord {
+                id,
+                rid: rid.to_string(),
+                name: format!("GUEST_{}", id),
+                pfp_blob,
+                pfp_ext: String::from(".png"),
+                rating_deviation: rating::DEFAULT_DEVIATION,
+                rating_volatility: rating::DEFAULT_VOLATILITY,
+                ..Default::default()
+            },
+        );
+
+        Ok(())
+    }
+
+    fn load_player_by_rid(&mut self, rid: &str) -> anyhow::Result<PlayerRecord> {
+        self.players
+            .values()
+            .find(|player| player.rid == rid)
+            .cloned()
+            .ok_or_else(|| anyhow!("Cannot find player with RID of {}", rid))
+    }
+
+    fn load_player_by_id(&mut self, id: i32) -> anyhow::Result<PlayerRecord> {
+        self.player(id).cloned()
+    }
+
+    fn save_player_name(&mut self, id: i32, name: &str) -> anyhow::Result<()> {
+        self.player_mut(id)?.name = name.to_string();
+
+        Ok(())
+    }
+
+    fn save_rank(&mut self, rank: &PlayerRank) -> anyhow::Result<()> {
+        let player = self.player_mut(rank.id)?;
+        player.rating = rank.rating;
+        player.rating_deviation = rank.rating_deviation;
+        player.rating_volatility = rank.rating_volatility;
+        player.win_count = rank.win_count;
+        player.lose_count = rank.lose_count;
+        player.draw_count = rank.draw_count;
+
+        Ok(())
+    }
+
+    fn get_pfp(&mut self, id: i32) -> anyhow::Result<(Vec<u8>, String)> {
+        let player = self
+            .player(id)
+            .map_err(|_| anyhow!("Cannot find pfp with ID of {}", id))?;
+
+        Ok((player.pfp_blob.clone(), player.pfp_ext.clone()))
+    }
+
+    fn get_pfp_by_name(&mut self, name: &str) -> anyhow::Result<(Vec<u8>, String)> {
+        let player = self
+            .player_by_name(name)
+            .ok_or_else(|| anyhow!("Cannot find pfp with Name of {}", name))?;
+
+        Ok((player.pfp_blob.clone(), player.pfp_ext.clone()))
+    }
+
+    fn update_profile_picture(
+        &mut self,
+        id: i32,
+        pfp_blob: &[u8],
+        extension: &str,
+    ) -> anyhow::Result<()> {
+        let player = self.player_mut(id)?;
+        player.pfp_blob = pfp_blob.to_vec();
+        player.pfp_ext = extension.to_string();
+
+        Ok(())
+    }
+
+    fn get_all_questions(&mut self) -> anyhow::Result<Vec<GameQuestion>> {
+        Ok(self.questions.clone())
+    }
+
+    fn insert_questions(&mut self, questions: &[GameQuestion]) -> anyhow::Result<()> {
+        let next_id = self.questions.iter().map(|x| x.id).max().unwrap_or(0) + 1;
+        self.questions.extend(
+            questions
+                .iter()
+                .zip(next_id..)
+                .map(|(question, id)| GameQuestion {
+                    id,
+                    ..question.clone()
+                }),
+        );
+
+        Ok(())
+    }
+
+    fn get_leaderboard(&mut self) -> anyhow::Result<Vec<LeaderboardInfo>> {
+        let mut players = self.players.values().collect::<Vec<_>>();
+        players.sort_by_key(|player| Reverse(player.rating));
+
+        Ok(players
+            .into_iter()
+            .take(10)
+            .map(|player| LeaderboardInfo {
+                win: player.win_count,
+                lose: player.lose_count,
+                draw: player.draw_count,
+                rating: player.rating,
+                name: player.name.clone(),
+                pfp_ext: player.pfp_ext.clone(),
+                pfp: player.pfp_blob.clone(),
+            })
+            .collect())
+    }
+
+    fn get_friends(&mut self, id: i32) -> anyhow::Result<Vec<FriendInfo>> {
+        Ok(self
+            .friends
+            .range((id, i32::MIN)..=(id, i32::MAX))
+            .filter_map(|(_, friend_id)| self.players.get(friend_id))
+            .map(|friend| FriendInfo {
+                id: friend.id,
+                name: friend.name.clone(),
+                online: false,
+                pfp_ext: String::new(),
+                pfp: Vec::new(),
+            })
+            .collect())
+    }
+
+    fn add_friend(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        self.check_pair(&self.friends, id, target)?;
+        self.friends.insert((id, target));
+
+        Ok(())
+    }
+
+    fn get_friend_requests(&mut self, id: i32) -> anyhow::Result<Vec<FriendRequestInfo>> {
+        Ok(self
+            .friend_requests
+            .range((id, i32::MIN)..=(id, i32::MAX))
+            .filter_map(|(_, friend_id)| self.players.get(friend_id))
+            .map(|friend| FriendRequestInfo {
+                id: friend.id,
+                name: friend.name.clone(),
+                pfp_ext: String::new(),
+                pfp: Vec::new(),
+            })
+            .collect())
+    }
+
+    fn add_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        self.check_pair(&self.friend_requests, id, target)?;
+        self.friend_requests.insert((id, target));
+
+        Ok(())
+    }
+
+    fn remove_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        self.friend_requests.remove(&(id, target));
+
+        Ok(())
+    }
+
+    fn is_username_taken(&mut self, username: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .players
+            .values()
+            .any(|player| player.username.as_deref() == Some(username)))
+    }
+
+    fn load_credentials(&mut self, username: &str) -> anyhow::Result<Option<(i32, String)>> {
+        Ok(self
+            .players
+            .values()
+            .find(|player| player.username.as_deref() == Some(username))
+            .and_then(|player| {
+                let hash = self.password_hashes.get(&player.id)?;
+                Some((player.id, hash.clone()))
+            }))
+    }
+
+    fn save_credentials(
+        &mut self,
+        id: i32,
+        username: &str,
+        password_hash: &str,
+    ) -> anyhow::Result<()> {
+        if self.is_username_taken(username)? {
+            return Err(anyhow!("Duplicate username {}", username));
+        }
+
+        self.player_mut(id)?.username = Some(username.to_string());
+        self.password_hashes.insert(id, password_hash.to_string());
+
+        Ok(())
+    }
+
+    fn insert_session_token(&mut self, token: &SessionToken) -> anyhow::Result<()> {
+        self.player(token.player_id)?;
+        self.session_tokens
+            .insert(token.token_hash.clone(), token.clone());
+
+        Ok(())
+    }
+
+    fn load_session_token(&mut self, token_hash: &str) -> anyhow::Result<Option<SessionToken>> {
+        Ok(self.session_tokens.get(token_hash).cloned())
+    }
+
+    fn remove_session_token(&mut self, token_hash: &str) -> anyhow::Result<()> {
+        self.session_tokens.remove(token_hash);
+
+        Ok(())
+    }
+
+    fn remove_session_tokens(&mut self, player_id: i32) -> anyhow::Result<usize> {
+        let count = self.session_tokens.len();
+        self.session_tokens
+            .retain(|_, token| token.player_id != player_id);
+
+        Ok(count - self.session_tokens.len())
+    }
+
+    fn remove_expired_session_tokens(&mut self, player_id: i32, now: i64) -> anyhow::Result<usize> {
+        let count = self.session_tokens.len();
+        self.session_tokens
+            .retain(|_, token| token.player_id != player_id || token.expires_at > now);
+
+        Ok(count - self.session_tokens.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> MemoryDatabase {
+        MemoryDatabase::new(&Config::default()).unwrap()
+    }
+
+    #[test]
+    fn new_player_is_a_guest() {
+        let mut db = database();
+        let record = db.load_or_insert_player("rid").unwrap();
+
+        assert_eq!(record.id, 1);
+        assert_eq!(record.name, "GUEST_1");
+        assert_eq!(record.username, None);
+        assert_eq!(record.rating_deviation, rating::DEFAULT_DEVIATION);
+
+        // The same RID loads the same player
+        assert_eq!(db.load_or_insert_player("rid").unwrap().id, 1);
+        assert_eq!(db.load_or_insert_player("other").unwrap().id, 2);
+    }
+
+    #[test]
+    fn save_rank_round_trip() {
+        let mut db = database();
+        let id = db.load_or_insert_player("rid").unwrap().id;
+
+        let rank = PlayerRank {
+            id,
+            rating: 1234,
+            rating_deviation: 80.5,
+            rating_volatility: 0.07,
+            win_count: 3,
+            lose_count: 2,
+            draw_count: 1,
+        };
+        db.save_rank(&rank).unwrap();
+
+        let record = db.load_player_by_id(id).unwrap();
+        assert_eq!(record.rating, 1234);
+        assert_eq!(record.rating_deviation, 80.5);
+        assert_eq!(record.rating_volatility, 0.07);
+        assert_eq!(
+            (record.win_count, record.lose_count, record.draw_count),
+            (3, 2, 1)
+        );
+
+        assert!(db
+            .save_rank(&PlayerRank {
+                id: 99,
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn accepting_a_friend_request_befriends_both() {
+        let mut db = database();
+        let alice = db.load_or_insert_player("alice").unwrap().id;
+        let bob = db.load_or_insert_player("bob").unwrap().id;
+
+        // Rows are (receiver, sender)
+        db.add_friend_request(bob, alice).unwrap();
+        assert!(db.add_friend_request(bob, alice).is_err());
+        assert!(db.add_friend_request(bob, 99).is_err());
+
+        let requests = db.get_friend_requests(bob).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].id, alice);
+        assert!(db.get_friend_requests(alice).unwrap().is_empty());
+
+        db.accept_friend_request(bob, alice).unwrap();
+        assert!(db.get_friend_requests(bob).unwrap().is_empty());
+        assert!(db.is_friend(alice, bob).unwrap());
+        assert!(db.is_friend(bob, alice).unwrap());
+
+        // Accepting again would add the friendship twice
+        assert!(db.accept_friend_request(bob, alice).is_err());
+    }
+
+    #[test]
+    fn declined_friend_request_is_gone() {
+        let mut db = database();
+        let alice = db.load_or_insert_player("alice").unwrap().id;
+        let bob = db.load_or_insert_player("bob").unwrap().id;
+
+        db.add_friend_request(bob, alice).unwrap();
+        db.remove_friend_request(bob, alice).unwrap();
+
+        assert!(db.get_friend_requests(bob).unwrap().is_empty());
+        assert!(!db.is_friend(bob, alice).unwrap());
+    }
+}
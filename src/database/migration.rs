@@ -0,0 +1,369 @@
+use anyhow::{anyhow, bail, Context};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::Path};
+
+/// A migration loaded from `<version>-<name>.sql`, with an optional
+/// `<version>-<name>.down.sql` to revert it.
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub up: String,
+    pub down: Option<String>,
+    /// Hex encoded SHA-256 of `up`
+    pub checksum: String,
+}
+
+/// A row of the `schema_migrations` table.
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+}
+
+/// Versions the runner applied before `schema_migrations` existed, with the
+/// table each of them creates.
+const LEGACY_MIGRATIONS: &[(i64, &str)] =
+    &[(0, "Players"), (1, "QuestionLists"), (2, "FriendLists")];
+
+/// What a backend has to provide for `run` and `rollback`. `apply` and
+/// `revert` run the SQL and update `schema_migrations` in a single
+/// transaction where the backend can roll back schema changes. Otherwise a
+/// failure has to name the migration and ask for a manual repair.
+pub trait MigrationConnection {
+    fn ensure_migrations_table(&mut self) -> anyhow::Result<()>;
+    fn applied_migrations(&mut self) -> anyhow::Result<Vec<AppliedMigration>>;
+    fn table_exists(&mut self, table: &str) -> anyhow::Result<bool>;
+    /// Records `migration` in `schema_migrations` without running it.
+    fn mark_applied(&mut self, migration: &Migration) -> anyhow::Result<()>;
+    fn apply(&mut self, migration: &Migration) -> anyhow::Result<()>;
+    fn revert(&mut self, migration: &Migration, down: &str) -> anyhow::Result<()>;
+}
+
+pub fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Loads every migration in `dir`, sorted by version.
+pub fn load(dir: &Path) -> anyhow::Result<Vec<Migration>> {
+    let mut ups = Vec::new();
+    let mut downs = HashMap::new();
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Cannot read directory {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|x| x.to_str()) else {
+            continue;
+        };
+
+        let Some(stem) = file_name.strip_suffix(".sql") else {
+            continue;
+        };
+
+        let (stem, is_down) = match stem.strip_suffix(".down") {
+            Some(stem) => (stem, true),
+            None => (stem, false),
+        };
+
+        let (version, name) = stem
+            .split_once('-')
+            .and_then(|(version, name)| Some((version.parse::<i64>().ok()?, name)))
+            .ok_or_else(|| anyhow!("Invalid migration file name {}", file_name))?;
+
+        let sql = std::fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read file {}", path.display()))?;
+
+        if is_down {
+            downs.insert(version, sql);
+        } else {
+            ups.push((version, name.to_string(), sql));
+        }
+    }
+
+    ups.sort_by_key(|(version, _, _)| *version);
+
+    let mut migrations: Vec<Migration> = Vec::new();
+    for (version, name, up) in ups {
+        if migrations
+            .last()
+            .is_some_and(|last| last.version == version)
+        {
+            bail!("Duplicate migration version {}", version);
+        }
+
+        migrations.push(Migration {
+            version,
+            name,
+            checksum: checksum(&up),
+            down: downs.remove(&version),
+            up,
+        });
+    }
+
+    if let Some(version) = downs.keys().next() {
+        bail!("Down migration for version {} has no up migration", version);
+    }
+
+    Ok(migrations)
+}
+
+/// Fails if an applied migration is missing on disk or was edited after it
+/// ran.
+fn verify(migrations: &[Migration], applied: &[AppliedMigration]) -> anyhow::Result<()> {
+    for applied in applied {
+        let Some(migration) = migrations.iter().find(|x| x.version == applied.version) else {
+            bail!(
+                "Migration {}-{} was applied but is missing on disk",
+                applied.version,
+                applied.name
+            );
+        };
+
+        if migration.checksum != applied.checksum {
+            bail!(
+                "Checksum mismatch for migration {}-{}: applied {}, on disk {}",
+                migration.version,
+                migration.name,
+                applied.checksum,
+                migration.checksum
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The applied migrations. A database set up before `schema_migrations`
+/// existed has the tables of the legacy migrations but no record of them,
+/// those are recorded as applied instead of being run again.
+fn applied_migrations(
+    con: &mut dyn MigrationConnection,
+    migrations: &[Migration],
+) -> anyhow::Result<Vec<AppliedMigration>> {
+    con.ensure_migrations_table()?;
+    let applied = con.applied_migrations()?;
+    if !applied.is_empty() {
+        return Ok(applied);
+    }
+
+    for (version, table) in LEGACY_MIGRATIONS {
+        let Some(migration) = migrations.iter().find(|x| x.version == *version) else {
+            continue;
+        };
+
+        if !con.table_exists(table)? {
+            continue;
+        }
+
+        println!(
+            "Migrations: {} already exists, recording {}-{} as applied",
+            table, migration.version, migration.name
+        );
+        con.mark_applied(migration)?;
+    }
+
+    con.applied_migrations()
+}
+
+/// Applies every pending migration in `dir`.
+pub fn run(con: &mut dyn MigrationConnection, dir: &Path) -> anyhow::Result<()> {
+    let migrations = load(dir)?;
+
+    let applied = applied_migrations(con, &migrations)?;
+    verify(&migrations, &applied)?;
+
+    let pending = migrations
+        .iter()
+        .filter(|x| !applied.iter().any(|applied| applied.version == x.version))
+        .collect::<Vec<_>>();
+
+    println!("Running {} migration(s)...", pending.len());
+
+    for migration in pending {
+        println!(
+            "Migrations: applying {}-{}",
+            migration.version, migration.name
+        );
+
+        con.apply(migration).with_context(|| {
+            format!(
+                "Failed to apply migration {}-{}",
+                migration.version, migration.name
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Reverts the last `steps` applied migrations, newest first.
+pub fn rollback(con: &mut dyn MigrationConnection, dir: &Path, steps: usize) -> anyhow::Result<()> {
+    let migrations = load(dir)?;
+
+    let mut applied = applied_migrations(con, &migrations)?;
+    verify(&migrations, &applied)?;
+
+    applied.sort_by_key(|x| std::cmp::Reverse(x.version));
+
+    for applied in applied.iter().take(steps) {
+        let migration = migrations
+            .iter()
+            .find(|x| x.version == applied.version)
+            .expect("verified above");
+
+        let Some(down) = &migration.down else {
+            bail!(
+                "Migration {}-{} has no down migration",
+                migration.version,
+                migration.name
+            );
+        };
+
+        println!(
+            "Migrations: reverting {}-{}",
+            migration.version, migration.name
+        );
+
+        con.revert(migration, down).with_context(|| {
+            format!(
+                "Failed to revert migration {}-{}",
+                migration.version, migration.name
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Prints every migration in `dir` and whether it has been applied.
+pub fn status(con: &mut dyn MigrationConnection, dir: &Path) -> anyhow::Result<()> {
+    let migrations = load(dir)?;
+
+    let applied = applied_migrations(con, &migrations)?;
+
+    for migration in &migrations {
+        let state = match applied.iter().find(|x| x.version == migration.version) {
+            Some(applied) if applied.checksum != migration.checksum => "checksum mismatch",
+            Some(_) => "applied",
+            None => "pending",
+        };
+
+        println!("{}-{}: {}", migration.version, migration.name, state);
+    }
+
+    for applied in &applied {
+        if !migrations.iter().any(|x| x.version == applied.version) {
+            println!("{}-{}: missing on disk", applied.version, applied.name);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Keeps track of what the runner did instead of touching a database.
+    #[derive(Default)]
+    struct FakeConnection {
+        tables: Vec<&'static str>,
+        applied: Vec<(i64, String, String)>,
+        ran: Vec<i64>,
+    }
+
+    impl MigrationConnection for FakeConnection {
+        fn ensure_migrations_table(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn applied_migrations(&mut self) -> anyhow::Result<Vec<AppliedMigration>> {
+            Ok(self
+                .applied
+                .iter()
+                .map(|(version, name, checksum)| AppliedMigration {
+                    version: *version,
+                    name: name.clone(),
+                    checksum: checksum.clone(),
+                })
+                .collect())
+        }
+
+        fn table_exists(&mut self, table: &str) -> anyhow::Result<bool> {
+            Ok(self.tables.contains(&table))
+        }
+
+        fn mark_applied(&mut self, migration: &Migration) -> anyhow::Result<()> {
+            self.applied.push((
+                migration.version,
+                migration.name.clone(),
+                migration.checksum.clone(),
+            ));
+            Ok(())
+        }
+
+        fn apply(&mut self, migration: &Migration) -> anyhow::Result<()> {
+            self.ran.push(migration.version);
+            self.mark_applied(migration)
+        }
+
+        fn revert(&mut self, migration: &Migration, _down: &str) -> anyhow::Result<()> {
+            self.applied.retain(|x| x.0 != migration.version);
+            Ok(())
+        }
+    }
+
+    fn migrations_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("runtime/migrations/sqlite")
+    }
+
+    #[test]
+    fn fresh_database_runs_every_migration() {
+        let mut con = FakeConnection::default();
+        run(&mut con, &migrations_dir()).unwrap();
+
+        let versions = load(&migrations_dir())
+            .unwrap()
+            .iter()
+            .map(|x| x.version)
+            .collect::<Vec<_>>();
+        assert_eq!(con.ran, versions);
+    }
+
+    #[test]
+    fn legacy_database_skips_legacy_migrations() {
+        let mut con = FakeConnection {
+            tables: vec!["Players", "QuestionLists", "FriendLists"],
+            ..Default::default()
+        };
+        run(&mut con, &migrations_dir()).unwrap();
+
+        assert!(con.ran.iter().all(|x| *x > 2));
+        assert!(con.ran.contains(&3));
+
+        let migrations = load(&migrations_dir()).unwrap();
+        for (version, _, checksum) in &con.applied {
+            let migration = migrations.iter().find(|x| x.version == *version).unwrap();
+            assert_eq!(*checksum, migration.checksum);
+        }
+
+        // A second run finds nothing to do.
+        con.ran.clear();
+        run(&mut con, &migrations_dir()).unwrap();
+        assert!(con.ran.is_empty());
+    }
+
+    #[test]
+    fn edited_migration_is_refused() {
+        let mut con = FakeConnection::default();
+        con.applied
+            .push((0, "create-player".to_string(), "edited".to_string()));
+
+        assert!(run(&mut con, &migrations_dir()).is_err());
+    }
+}
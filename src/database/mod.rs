@@ -0,0 +1,311 @@
+pub mod memory;
+pub mod migration;
+pub mod mysql;
+pub mod pool;
+pub mod questions;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use crate::{
+    config::{Config, DatabaseBackend, MigrateAction, QuestionAction},
+    state::GameQuestion,
+};
+use anyhow::Context;
+
+pub struct FriendInfo {
+    pub id: i32,
+    pub name: String,
+    pub online: bool,
+    pub pfp_ext: String,
+    pub pfp: Vec<u8>,
+}
+
+pub struct FriendRequestInfo {
+    pub id: i32,
+    pub name: String,
+    pub pfp_ext: String,
+    pub pfp: Vec<u8>,
+}
+
+pub struct LeaderboardInfo {
+    pub win: i32,
+    pub lose: i32,
+    pub draw: i32,
+    pub rating: i32,
+
+    pub name: String,
+    pub pfp_ext: String,
+
+    pub pfp: Vec<u8>,
+}
+
+/// A row of the `Players` table.
+#[derive(Default, Debug, Clone)]
+pub struct PlayerRecord {
+    pub id: i32,
+    pub rid: String,
+    pub name: String,
+    pub rating: i32,
+    pub rating_deviation: f64,
+    pub rating_volatility: f64,
+    pub win_count: i32,
+    pub lose_count: i32,
+    pub draw_count: i32,
+    pub pfp_blob: Vec<u8>,
+    pub pfp_ext: String,
+    /// Set once the player registers a password, guests have none
+    pub username: Option<String>,
+}
+
+impl PlayerRecord {
+    /// Takes over `rank` if it has more matches than the record, i.e. it is
+    /// the result of a match the record was loaded before.
+    pub fn apply_rank(&mut self, rank: &PlayerRank) {
+        if rank.games() <= self.win_count + self.lose_count + self.draw_count {
+            return;
+        }
+
+        self.rating = rank.rating;
+        self.rating_deviation = rank.rating_deviation;
+        self.rating_volatility = rank.rating_volatility;
+        self.win_count = rank.win_count;
+        self.lose_count = rank.lose_count;
+        self.draw_count = rank.draw_count;
+    }
+}
+
+/// A row of the `SessionTokens` table. Only the SHA-256 of the token is
+/// stored, see `auth::hash_token`.
+#[derive(Debug, Clone)]
+pub struct SessionToken {
+    pub token_hash: String,
+    pub player_id: i32,
+    /// Unix time in seconds
+    pub expires_at: i64,
+}
+
+/// The match result columns of the `Players` table written by `save_rank`.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PlayerRank {
+    pub id: i32,
+    pub rating: i32,
+    pub rating_deviation: f64,
+    pub rating_volatility: f64,
+    pub win_count: i32,
+    pub lose_count: i32,
+    pub draw_count: i32,
+}
+
+impl PlayerRank {
+    /// Matches played, grows with every save.
+    pub fn games(&self) -> i32 {
+        self.win_count + self.lose_count + self.draw_count
+    }
+}
+
+/// Everything the server needs from persistent storage. Implementations are
+/// moved onto the database worker threads, see `pool::DatabasePool`.
+pub trait Storage: Send {
+    fn is_player_exist_by_rid(&mut self, rid: &str) -> anyhow::Result<bool>;
+    fn is_player_exist_by_name(&mut self, name: &str) -> anyhow::Result<bool>;
+    fn insert_new_player(&mut self, rid: &str) -> anyhow::Result<()>;
+    fn load_player_by_rid(&mut self, rid: &str) -> anyhow::Result<PlayerRecord>;
+    fn load_player_by_id(&mut self, id: i32) -> anyhow::Result<PlayerRecord>;
+    fn save_player_name(&mut self, id: i32, name: &str) -> anyhow::Result<()>;
+    fn save_rank(&mut self, rank: &PlayerRank) -> anyhow::Result<()>;
+
+    fn get_pfp(&mut self, id: i32) -> anyhow::Result<(Vec<u8>, String)>;
+    fn get_pfp_by_name(&mut self, name: &str) -> anyhow::Result<(Vec<u8>, String)>;
+    fn update_profile_picture(
+        &mut self,
+        id: i32,
+        pfp_blob: &[u8],
+        extension: &str,
+    ) -> anyhow::Result<()>;
+
+    fn get_all_questions(&mut self) -> anyhow::Result<Vec<GameQuestion>>;
+    /// Adds every question or, if one fails, none of them. IDs are ignored.
+    fn insert_questions(&mut self, questions: &[GameQuestion]) -> anyhow::Result<()>;
+    fn get_leaderboard(&mut self) -> anyhow::Result<Vec<LeaderboardInfo>>;
+
+    fn get_friends(&mut self, id: i32) -> anyhow::Result<Vec<FriendInfo>>;
+    fn add_friend(&mut self, id: i32, target: i32) -> anyhow::Result<()>;
+
+    fn get_friend_requests(&mut self, id: i32) -> anyhow::Result<Vec<FriendRequestInfo>>;
+    fn add_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()>;
+    fn remove_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()>;
+
+    fn is_username_taken(&mut self, username: &str) -> anyhow::Result<bool>;
+    /// Returns the player ID and password hash registered for `username`.
+    fn load_credentials(&mut self, username: &str) -> anyhow::Result<Option<(i32, String)>>;
+    fn save_credentials(
+        &mut self,
+        id: i32,
+        username: &str,
+        password_hash: &str,
+    ) -> anyhow::Result<()>;
+
+    fn insert_session_token(&mut self, token: &SessionToken) -> anyhow::Result<()>;
+    fn load_session_token(&mut self, token_hash: &str) -> anyhow::Result<Option<SessionToken>>;
+    fn remove_session_token(&mut self, token_hash: &str) -> anyhow::Result<()>;
+    /// Revokes every token of the player, returns how many there were.
+    fn remove_session_tokens(&mut self, player_id: i32) -> anyhow::Result<usize>;
+    /// Removes the tokens of the player that expired before `now`, in Unix
+    /// time, and returns how many there were.
+    fn remove_expired_session_tokens(&mut self, player_id: i32, now: i64) -> anyhow::Result<usize>;
+
+    /// Loads the player with `rid`, registering a new guest if there is none.
+    fn load_or_insert_player(&mut self, rid: &str) -> anyhow::Result<PlayerRecord> {
+        let exist = self
+            .is_player_exist_by_rid(rid)
+            .context("is_player_exist_by_rid fails")?;
+
+        if !exist {
+            self.insert_new_player(rid)
+                .context("insert_new_player fails")?;
+        }
+
+        self.load_player_by_rid(rid)
+            .context("load_player_by_rid fails")
+    }
+
+    /// Renames the player unless the name is taken, returns whether it was.
+    fn try_rename_player(&mut self, id: i32, name: &str) -> anyhow::Result<bool> {
+        let taken = self
+            .is_player_exist_by_name(name)
+            .context("is_player_exist_by_name fails")?;
+
+        if !taken {
+            self.save_player_name(id, name)
+                .context("save_player_name fails")?;
+        }
+
+        Ok(taken)
+    }
+
+    /// Registers credentials unless the username is taken, returns whether it
+    /// was.
+    fn try_save_credentials(
+        &mut self,
+        id: i32,
+        username: &str,
+        password_hash: &str,
+    ) -> anyhow::Result<bool> {
+        let taken = self
+            .is_username_taken(username)
+            .context("is_username_taken fails")?;
+
+        if !taken {
+            self.save_credentials(id, username, password_hash)
+                .context("save_credentials fails")?;
+        }
+
+        Ok(taken)
+    }
+
+    fn is_friend(&mut self, id: i32, target: i32) -> anyhow::Result<bool> {
+        let friends = self.get_friends(id).context("get_friends fails")?;
+        Ok(friends.iter().any(|x| x.id == target))
+    }
+
+    fn get_friends_with_pfp(&mut self, id: i32) -> anyhow::Result<Vec<FriendInfo>> {
+        let mut friends = self.get_friends(id)?;
+        for friend in friends.iter_mut() {
+            (friend.pfp, friend.pfp_ext) = self.get_pfp(friend.id)?;
+        }
+
+        Ok(friends)
+    }
+
+    fn get_friend_requests_with_pfp(&mut self, id: i32) -> anyhow::Result<Vec<FriendRequestInfo>> {
+        let mut friend_requests = self.get_friend_requests(id)?;
+        for friend in friend_requests.iter_mut() {
+            (friend.pfp, friend.pfp_ext) = self.get_pfp(friend.id)?;
+        }
+
+        Ok(friend_requests)
+    }
+
+    /// `id` accepts the friend request sent by `target`.
+    fn accept_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        self.remove_friend_request(id, target)
+            .context("Remove friend request error")?;
+        self.add_friend(id, target).context("Add friend #1 error")?;
+        self.add_friend(target, id).context("Add friend #2 error")?;
+
+        Ok(())
+    }
+}
+
+/// Opens the storage backend selected in the configuration, with one handle
+/// per database worker. The memory backend always gets a single worker since
+/// its data lives in the handle itself.
+pub fn open(config: &Config) -> anyhow::Result<Vec<Box<dyn Storage>>> {
+    let workers = config.database.workers;
+
+    match config.database.backend {
+        DatabaseBackend::Mysql => {
+            let database = mysql::MysqlDatabase::new(config)?;
+
+            Ok((0..workers)
+                .map(|_| Box::new(database.clone()) as Box<dyn Storage>)
+                .collect())
+        }
+        DatabaseBackend::Memory => Ok(vec![Box::new(memory::MemoryDatabase::new(config)?)]),
+        #[cfg(feature = "sqlite")]
+        DatabaseBackend::Sqlite => {
+            let mut storages: Vec<Box<dyn Storage>> =
+                vec![Box::new(sqlite::SqliteDatabase::new(config)?)];
+
+            for _ in 1..workers {
+                storages.push(Box::new(sqlite::SqliteDatabase::open(config)?));
+            }
+
+            Ok(storages)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        DatabaseBackend::Sqlite => Err(anyhow::anyhow!(
+            "SQLite backend requested but the server was built without the `sqlite` feature"
+        )),
+    }
+}
+
+/// Runs a `migrate` subcommand against the configured backend.
+pub fn run_migration_command(config: &Config, action: &MigrateAction) -> anyhow::Result<()> {
+    let dir = config.server.migrations_dir(config.database.backend);
+    let mut con: Box<dyn migration::MigrationConnection> = match config.database.backend {
+        DatabaseBackend::Mysql => Box::new(mysql::MysqlDatabase::connect(config)?),
+        #[cfg(feature = "sqlite")]
+        DatabaseBackend::Sqlite => Box::new(sqlite::SqliteDatabase::connect(config)?),
+        #[cfg(not(feature = "sqlite"))]
+        DatabaseBackend::Sqlite => anyhow::bail!(
+            "SQLite backend requested but the server was built without the `sqlite` feature"
+        ),
+        DatabaseBackend::Memory => anyhow::bail!("The memory backend has no migrations"),
+    };
+
+    match action {
+        MigrateAction::Up => migration::run(con.as_mut(), &dir),
+        MigrateAction::Down { steps } => migration::rollback(con.as_mut(), &dir, *steps),
+        MigrateAction::Status => migration::status(con.as_mut(), &dir),
+    }
+}
+
+/// Runs a `questions` subcommand against the configured backend.
+pub fn run_question_command(config: &Config, action: &QuestionAction) -> anyhow::Result<()> {
+    if config.database.backend == DatabaseBackend::Memory {
+        anyhow::bail!("The memory backend has no question bank to import to or export from");
+    }
+
+    let mut storages = open(config)?;
+    let storage = storages[0].as_mut();
+
+    match action {
+        QuestionAction::Import {
+            path,
+            format,
+            dry_run,
+        } => questions::import(storage, path, *format, *dry_run),
+        QuestionAction::Export { path, format } => questions::export(storage, path, *format),
+    }
+}
@@ -0,0 +1,602 @@
+use super::{
+    migration::{self, AppliedMigration, Migration, MigrationConnection},
+    FriendInfo, FriendRequestInfo, LeaderboardInfo, PlayerRank, PlayerRecord, SessionToken,
+    Storage,
+};
+use crate::{
+    config::{Config, DatabaseBackend},
+    state::GameQuestion,
+};
+use anyhow::anyhow;
+use mysql::{prelude::Queryable, Conn, OptsBuilder, Pool, PoolConstraints, PoolOpts, Row, TxOpts};
+use std::{path::PathBuf, time::Duration};
+
+/// Cloning is cheap, clones share the same connection pool.
+#[derive(Clone)]
+pub struct MysqlDatabase {
+    pub pool: Pool,
+    pub empty_pfp_path: PathBuf,
+}
+
+impl MysqlDatabase {
+    /// Broken connections are dropped by the pool and replaced on the next
+    /// query, so a restarted MySQL server does not take the game server down.
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let workers = config.database.workers.max(1);
+        let constraints = PoolConstraints::new(1, workers)
+            .ok_or_else(|| anyhow!("Invalid pool size {}", workers))?;
+        let pool_opts = PoolOpts::default()
+            .with_constraints(constraints)
+            .with_check_health(true);
+
+        let pool = Pool::new(Self::opts(config).pool_opts(pool_opts))?;
+        migration::run(
+            pool.get_conn()?.as_mut(),
+            &config.server.migrations_dir(DatabaseBackend::Mysql),
+        )?;
+
+        Ok(Self {
+            pool,
+            empty_pfp_path: config.server.empty_profile_picture(),
+        })
+    }
+
+    pub fn connect(config: &Config) -> anyhow::Result<Conn> {
+        Ok(Conn::new(Self::opts(config))?)
+    }
+
+    fn opts(config: &Config) -> OptsBuilder {
+        let db_config = &config.database;
+        OptsBuilder::new()
+            .user(Some(&db_config.user))
+            .pass(Some(&db_config.pass))
+            .ip_or_hostname(Some(&db_config.host))
+            .tcp_port(db_config.port)
+            .db_name(Some(&db_config.name))
+            .tcp_connect_timeout(Some(Duration::from_secs(db_config.connect_timeout_secs)))
+    }
+
+    pub fn load_player_from_row(row: Row) -> Option<PlayerRecord> {
+        Some(PlayerRecord {
+            id: row.get(0)?,
+            rid: row.get(1)?,
+            name: row.get(2)?,
+            rating: row.get(3)?,
+            win_count: row.get(4)?,
+            lose_count: row.get(5)?,
+            pfp_blob: row.get(6)?,
+            pfp_ext: row.get(7)?,
+            username: row.get(8)?,
+            rating_deviation: row.get(10)?,
+            rating_volatility: row.get(11)?,
+            draw_count: row.get(12)?,
+        })
+    }
+
+    pub fn load_question_from_row(row: Row) -> Option<GameQuestion> {
+        Some(GameQuestion {
+            id: row.get(0)?,
+            question: row.get(1)?,
+            answer_option_1: row.get(2)?,
+            answer_option_2: row.get(3)?,
+            answer_option_3: row.get(4)?,
+            answer_option_4: row.get(5)?,
+            answer_index: row.get(6)?,
+            category: row.get(7)?,
+            difficulty: row.get(8)?,
+            language: row.get(9)?,
+        })
+    }
+
+    pub fn load_leaderboard_from_row(row: Row) -> Option<LeaderboardInfo> {
+        Some(LeaderboardInfo {
+            name: row.get(2)?,
+            rating: row.get(3)?,
+            win: row.get(4)?,
+            lose: row.get(5)?,
+            draw: row.get(12)?,
+            pfp: row.get(6)?,
+            pfp_ext: row.get(7)?,
+        })
+    }
+}
+
+impl Storage for MysqlDatabase {
+    fn save_player_name(&mut self, id: i32, name: &str) -> anyhow::Result<()> {
+        self.pool
+            .get_conn()?
+            .exec_drop("UPDATE Players SET Name = ? WHERE ID = ?;", (name, id))?;
+
+        Ok(())
+    }
+
+    fn insert_new_player(&mut self, rid: &str) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO Players (
+                RID,
+                ProfilePicture
+            ) VALUES (
+                ?,
+                ?
+            ) 
+            RETURNING ID;
+        ";
+
+        let data = std::fs::read(&self.empty_pfp_path)?;
+        let id: Option<i32> = self.pool.get_conn()?.exec_first(QUERY, (rid, data))?;
+
+        if let Some(id) = id {
+            self.save_player_name(id, &format!("GUEST_{}", id))?;
+
+            Ok(())
+        } else {
+            Err(anyhow!("Insert player returns none"))
+        }
+    }
+
+    fn is_player_exist_by_rid(&mut self, rid: &str) -> anyhow::Result<bool> {
+        const QUERY: &str = "SELECT EXISTS(SELECT 1 FROM Players WHERE RID = ? LIMIT 1);";
+
+        if let Some(row) = self.pool.get_conn()?.exec_first(QUERY, (rid,))? {
+            Ok(row)
+        } else {
+            Err(anyhow!("Cannot find player with RID of {}", rid))
+        }
+    }
+
+    fn is_player_exist_by_name(&mut self, name: &str) -> anyhow::Result<bool> {
+        const QUERY: &str = "SELECT EXISTS(SELECT 1 FROM Players WHERE Name = ? LIMIT 1);";
+
+        if let Some(row) = self.pool.get_conn()?.exec_first(QUERY, (name,))? {
+            Ok(row)
+        } else {
+            Err(anyhow!("Cannot find player with Name of {}", name))
+        }
+    }
+
+    fn load_player_by_rid(&mut self, rid: &str) -> anyhow::Result<PlayerRecord> {
+        const QUERY: &str = "SELECT * FROM Players WHERE RID = ? LIMIT 1;";
+
+        let row: Option<Row> = self.pool.get_conn()?.exec_first(QUERY, (rid,))?;
+
+        if let Some(row) = row {
+            Self::load_player_from_row(row).ok_or_else(|| anyhow!("Cannot load player"))
+        } else {
+            Err(anyhow!("Cannot find player with RID of {}", rid))
+        }
+    }
+
+    fn load_player_by_id(&mut self, id: i32) -> anyhow::Result<PlayerRecord> {
+        const QUERY: &str = "SELECT * FROM Players WHERE ID = ? LIMIT 1;";
+
+        let row: Option<Row> = self.pool.get_conn()?.exec_first(QUERY, (id,))?;
+
+        if let Some(row) = row {
+            Self::load_player_from_row(row).ok_or_else(|| anyhow!("Cannot load player"))
+        } else {
+            Err(anyhow!("Cannot find player with ID of {}", id))
+        }
+    }
+
+    fn save_rank(&mut self, rank: &PlayerRank) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            UPDATE Players SET
+                Rating = ?,
+                RatingDeviation = ?,
+                RatingVolatility = ?,
+                WinCount = ?,
+                LoseCount = ?,
+                DrawCount = ?
+            WHERE ID = ?;";
+
+        self.pool.get_conn()?.exec_drop(
+            QUERY,
+            (
+                rank.rating,
+                rank.rating_deviation,
+                rank.rating_volatility,
+                rank.win_count,
+                rank.lose_count,
+                rank.draw_count,
+                rank.id,
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    fn get_all_questions(&mut self) -> anyhow::Result<Vec<GameQuestion>> {
+        let rows: Vec<Row> = self
+            .pool
+            .get_conn()?
+            .query("SELECT * FROM QuestionLists;")?;
+        let mut questions = Vec::new();
+
+        for row in rows {
+            questions.push(
+                Self::load_question_from_row(row)
+                    .ok_or_else(|| anyhow!("Failed to load question from row"))?,
+            );
+        }
+
+        Ok(questions)
+    }
+
+    fn insert_questions(&mut self, questions: &[GameQuestion]) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO QuestionLists (
+                Question,
+                AnswerOption1,
+                AnswerOption2,
+                AnswerOption3,
+                AnswerOption4,
+                AnswerIndex,
+                Category,
+                Difficulty,
+                Language
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);";
+
+        let mut con = self.pool.get_conn()?;
+        let mut tx = con.start_transaction(TxOpts::default())?;
+        tx.exec_batch(
+            QUERY,
+            questions.iter().map(|x| {
+                (
+                    &x.question,
+                    &x.answer_option_1,
+                    &x.answer_option_2,
+                    &x.answer_option_3,
+                    &x.answer_option_4,
+                    x.answer_index,
+                    &x.category,
+                    x.difficulty,
+                    &x.language,
+                )
+            }),
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn get_leaderboard(&mut self) -> anyhow::Result<Vec<LeaderboardInfo>> {
+        let rows: Vec<Row> = self
+            .pool
+            .get_conn()?
+            .query("SELECT * FROM Players ORDER BY Rating DESC LIMIT 10;")?;
+
+        let mut leaderboard = Vec::new();
+        for row in rows {
+            leaderboard.push(
+                Self::load_leaderboard_from_row(row)
+                    .ok_or_else(|| anyhow!("Load leaderboard fails"))?,
+            );
+        }
+
+        Ok(leaderboard)
+    }
+
+    fn get_pfp(&mut self, id: i32) -> anyhow::Result<(Vec<u8>, String)> {
+        const QUERY: &str = "
+            SELECT ProfilePicture, ProfilePictureExtension
+            FROM Players
+            WHERE ID = ?
+            LIMIT 1;
+        ";
+
+        let row: Option<(Vec<u8>, String)> = self.pool.get_conn()?.exec_first(QUERY, (id,))?;
+        if let Some(row) = row {
+            Ok(row)
+        } else {
+            Err(anyhow!("Cannot find pfp with ID of {}", id))
+        }
+    }
+
+    fn get_pfp_by_name(&mut self, name: &str) -> anyhow::Result<(Vec<u8>, String)> {
+        const QUERY: &str = "
+            SELECT ProfilePicture, ProfilePictureExtension
+            FROM Players
+            WHERE Name = ?
+            LIMIT 1;
+        ";
+
+        let row: Option<(Vec<u8>, String)> = self.pool.get_conn()?.exec_first(QUERY, (name,))?;
+        if let Some(row) = row {
+            Ok(row)
+        } else {
+            Err(anyhow!("Cannot find pfp with Name of {}", name))
+        }
+    }
+
+    fn get_friends(&mut self, id: i32) -> anyhow::Result<Vec<FriendInfo>> {
+        const QUERY: &str = "
+            SELECT Players.ID, Players.Name
+            FROM FriendLists
+            INNER JOIN Players ON FriendLists.FriendID = Players.ID
+            WHERE FriendLists.PlayerID = ?
+        ";
+
+        let rows: Vec<(i32, String)> = self.pool.get_conn()?.exec(QUERY, (id,))?;
+
+        let mut friends = Vec::new();
+        for (id, name) in rows {
+            let friend = FriendInfo {
+                id,
+                name,
+                pfp_ext: String::new(),
+                pfp: Vec::new(),
+                online: false,
+            };
+            friends.push(friend);
+        }
+
+        Ok(friends)
+    }
+
+    fn add_friend(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO FriendLists (PlayerID, FriendID)
+            VALUES (?, ?)
+        ";
+
+        self.pool.get_conn()?.exec_drop(QUERY, (id, target))?;
+
+        Ok(())
+    }
+
+    fn get_friend_requests(&mut self, id: i32) -> anyhow::Result<Vec<FriendRequestInfo>> {
+        const QUERY: &str = "
+            SELECT Players.ID, Players.Name
+            FROM FriendRequests
+            INNER JOIN Players ON FriendRequests.FriendID = Players.ID
+            WHERE FriendRequests.PlayerID = ?
+        ";
+
+        let rows: Vec<(i32, String)> = self.pool.get_conn()?.exec(QUERY, (id,))?;
+
+        let mut friend_requests = Vec::new();
+        for (id, name) in rows {
+            let friend = FriendRequestInfo {
+                id,
+                name,
+                pfp_ext: String::new(),
+                pfp: Vec::new(),
+            };
+            friend_requests.push(friend);
+        }
+
+        Ok(friend_requests)
+    }
+
+    fn add_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO FriendRequests (PlayerID, FriendID, DateRequested)
+            VALUES (?, ?, CURDATE())
+        ";
+
+        self.pool.get_conn()?.exec_drop(QUERY, (id, target))?;
+
+        Ok(())
+    }
+
+    fn remove_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            DELETE FROM FriendRequests
+            WHERE PlayerID = ? AND FriendID = ?
+        ";
+
+        self.pool.get_conn()?.exec_drop(QUERY, (id, target))?;
+
+        Ok(())
+    }
+
+    fn update_profile_picture(
+        &mut self,
+        id: i32,
+        pfp_blob: &[u8],
+        extension: &str,
+    ) -> anyhow::Result<()> {
+        self.pool.get_conn()?.exec_drop(
+            "UPDATE Players SET ProfilePicture = ?, ProfilePictureExtension = ? WHERE ID = ?;",
+            (pfp_blob, extension, id),
+        )?;
+
+        Ok(())
+    }
+
+    fn is_username_taken(&mut self, username: &str) -> anyhow::Result<bool> {
+        const QUERY: &str = "SELECT EXISTS(SELECT 1 FROM Players WHERE Username = ? LIMIT 1);";
+
+        if let Some(row) = self.pool.get_conn()?.exec_first(QUERY, (username,))? {
+            Ok(row)
+        } else {
+            Err(anyhow!("Cannot check username {}", username))
+        }
+    }
+
+    fn load_credentials(&mut self, username: &str) -> anyhow::Result<Option<(i32, String)>> {
+        const QUERY: &str = "
+            SELECT ID, PasswordHash
+            FROM Players
+            WHERE Username = ? AND PasswordHash IS NOT NULL
+            LIMIT 1;
+        ";
+
+        Ok(self.pool.get_conn()?.exec_first(QUERY, (username,))?)
+    }
+
+    fn save_credentials(
+        &mut self,
+        id: i32,
+        username: &str,
+        password_hash: &str,
+    ) -> anyhow::Result<()> {
+        self.pool.get_conn()?.exec_drop(
+            "UPDATE Players SET Username = ?, PasswordHash = ? WHERE ID = ?;",
+            (username, password_hash, id),
+        )?;
+
+        Ok(())
+    }
+
+    fn insert_session_token(&mut self, token: &SessionToken) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO SessionTokens (TokenHash, PlayerID, ExpiresAt)
+            VALUES (?, ?, ?)
+        ";
+
+        self.pool.get_conn()?.exec_drop(
+            QUERY,
+            (&token.token_hash, token.player_id, token.expires_at),
+        )?;
+
+        Ok(())
+    }
+
+    fn load_session_token(&mut self, token_hash: &str) -> anyhow::Result<Option<SessionToken>> {
+        const QUERY: &str = "
+            SELECT TokenHash, PlayerID, ExpiresAt
+            FROM SessionTokens
+            WHERE TokenHash = ?
+            LIMIT 1;
+        ";
+
+        let row: Option<(String, i32, i64)> =
+            self.pool.get_conn()?.exec_first(QUERY, (token_hash,))?;
+
+        Ok(row.map(|(token_hash, player_id, expires_at)| SessionToken {
+            token_hash,
+            player_id,
+            expires_at,
+        }))
+    }
+
+    fn remove_session_token(&mut self, token_hash: &str) -> anyhow::Result<()> {
+        self.pool.get_conn()?.exec_drop(
+            "DELETE FROM SessionTokens WHERE TokenHash = ?;",
+            (token_hash,),
+        )?;
+
+        Ok(())
+    }
+
+    fn remove_session_tokens(&mut self, player_id: i32) -> anyhow::Result<usize> {
+        let mut con = self.pool.get_conn()?;
+        con.exec_drop(
+            "DELETE FROM SessionTokens WHERE PlayerID = ?;",
+            (player_id,),
+        )?;
+
+        Ok(con.affected_rows() as usize)
+    }
+
+    fn remove_expired_session_tokens(&mut self, player_id: i32, now: i64) -> anyhow::Result<usize> {
+        let mut con = self.pool.get_conn()?;
+        con.exec_drop(
+            "DELETE FROM SessionTokens WHERE PlayerID = ? AND ExpiresAt <= ?;",
+            (player_id, now),
+        )?;
+
+        Ok(con.affected_rows() as usize)
+    }
+}
+
+/// MySQL commits DDL statements implicitly, a transaction would not undo the
+/// statements of a migration that ran before the failing one. Migrations run
+/// without one and a failure asks for the database to be repaired by hand.
+impl MigrationConnection for Conn {
+    fn ensure_migrations_table(&mut self) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                Version BIGINT NOT NULL PRIMARY KEY,
+                Name VARCHAR(255) NOT NULL,
+                Checksum CHAR(64) NOT NULL,
+                AppliedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+        ";
+
+        self.query_drop(QUERY)?;
+
+        Ok(())
+    }
+
+    fn applied_migrations(&mut self) -> anyhow::Result<Vec<AppliedMigration>> {
+        let rows: Vec<(i64, String, String)> =
+            self.query("SELECT Version, Name, Checksum FROM schema_migrations ORDER BY Version;")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(version, name, checksum)| AppliedMigration {
+                version,
+                name,
+                checksum,
+            })
+            .collect())
+    }
+
+    fn table_exists(&mut self, table: &str) -> anyhow::Result<bool> {
+        let count: Option<i64> = self.exec_first(
+            "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = ?;",
+            (table,),
+        )?;
+
+        Ok(count.unwrap_or(0) > 0)
+    }
+
+    fn mark_applied(&mut self, migration: &Migration) -> anyhow::Result<()> {
+        self.exec_drop(
+            "INSERT INTO schema_migrations (Version, Name, Checksum) VALUES (?, ?, ?);",
+            (migration.version, &migration.name, &migration.checksum),
+        )?;
+
+        Ok(())
+    }
+
+    fn apply(&mut self, migration: &Migration) -> anyhow::Result<()> {
+        self.query_drop(&migration.up)
+            .map_err(|error| partly_run(migration, "applied", error))?;
+
+        self.exec_drop(
+            "INSERT INTO schema_migrations (Version, Name, Checksum) VALUES (?, ?, ?);",
+            (migration.version, &migration.name, &migration.checksum),
+        )
+        .map_err(|error| not_recorded(migration, "applied", error))?;
+
+        Ok(())
+    }
+
+    fn revert(&mut self, migration: &Migration, down: &str) -> anyhow::Result<()> {
+        self.query_drop(down)
+            .map_err(|error| partly_run(migration, "reverted", error))?;
+
+        self.exec_drop(
+            "DELETE FROM schema_migrations WHERE Version = ?;",
+            (migration.version,),
+        )
+        .map_err(|error| not_recorded(migration, "reverted", error))?;
+
+        Ok(())
+    }
+}
+
+fn partly_run(migration: &Migration, action: &str, error: mysql::Error) -> anyhow::Error {
+    anyhow!(
+        "{}. Migration {}-{} may be partly {}, MySQL cannot roll back schema changes. \
+         Repair the database manually before running migrations again",
+        error,
+        migration.version,
+        migration.name,
+        action
+    )
+}
+
+fn not_recorded(migration: &Migration, action: &str, error: mysql::Error) -> anyhow::Error {
+    anyhow!(
+        "{}. Migration {}-{} was {} but schema_migrations was not updated, \
+         update it manually before running migrations again",
+        error,
+        migration.version,
+        migration.name,
+        action
+    )
+}
@@ -0,0 +1,407 @@
+use super::{FriendInfo, FriendRequestInfo, LeaderboardInfo, PlayerRank, Storage};
+use crate::{
+    auth::{IssuedToken, Session},
+    config::MatchLength,
+    state::GameQuestion,
+};
+use enet::PeerID;
+use std::{
+    cell::Cell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    panic::AssertUnwindSafe,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// A query run on one of the database workers. The returned event, if any,
+/// is handed back to the game loop through `DatabasePool::poll`.
+pub type Job = Box<dyn FnOnce(&mut dyn Storage) -> Option<DatabaseEvent> + Send>;
+
+/// The connection a job was queued for. PeerIDs are handed out again once a
+/// peer disconnects, the serial tells the connections apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Requester {
+    pub peer_id: PeerID,
+    pub serial: u64,
+}
+
+/// Results of jobs posted back to the game loop. Events of player requests
+/// carry the connection that asked for it, the player may have disconnected
+/// in the meantime.
+pub enum DatabaseEvent {
+    /// Fails with `auth::InvalidCredentials` if the credentials were wrong
+    Authenticated {
+        requester: Requester,
+        result: anyhow::Result<Session>,
+    },
+    /// `result` is whether the username is already taken
+    CredentialsRegistered {
+        requester: Requester,
+        username: String,
+        result: anyhow::Result<bool>,
+    },
+    TokenRefreshed {
+        requester: Requester,
+        result: anyhow::Result<IssuedToken>,
+    },
+    /// `result` is the number of revoked tokens
+    TokensRevoked {
+        requester: Requester,
+        result: anyhow::Result<usize>,
+    },
+    Leaderboard {
+        requester: Requester,
+        result: anyhow::Result<Vec<LeaderboardInfo>>,
+    },
+    ProfilePictureChanged {
+        requester: Requester,
+        pfp_blob: Vec<u8>,
+        extension: String,
+        result: anyhow::Result<()>,
+    },
+    /// `result` is whether the name is already taken
+    NameUpdated {
+        requester: Requester,
+        name: String,
+        result: anyhow::Result<bool>,
+    },
+    Friends {
+        requester: Requester,
+        result: anyhow::Result<Vec<FriendInfo>>,
+    },
+    FriendRequests {
+        requester: Requester,
+        result: anyhow::Result<Vec<FriendRequestInfo>>,
+    },
+    /// `result` is whether `target` is a friend of the challenger
+    FriendChallenged {
+        requester: Requester,
+        target: i32,
+        length: MatchLength,
+        result: anyhow::Result<bool>,
+    },
+    /// `result` is the profile picture of the player, if one was found
+    SearchName {
+        requester: Requester,
+        name: String,
+        result: anyhow::Result<Option<(Vec<u8>, String)>>,
+    },
+    /// A job without a result of its own failed
+    Failed {
+        requester: Requester,
+        /// The request that queued the job
+        packet_id: u32,
+        context: &'static str,
+        error: anyhow::Error,
+    },
+    /// The periodic reload of the question bank, not asked for by a player
+    QuestionsLoaded {
+        result: anyhow::Result<Vec<GameQuestion>>,
+    },
+}
+
+impl DatabaseEvent {
+    pub fn requester(&self) -> Option<Requester> {
+        match self {
+            Self::Authenticated { requester, .. }
+            | Self::CredentialsRegistered { requester, .. }
+            | Self::TokenRefreshed { requester, .. }
+            | Self::TokensRevoked { requester, .. }
+            | Self::Leaderboard { requester, .. }
+            | Self::ProfilePictureChanged { requester, .. }
+            | Self::NameUpdated { requester, .. }
+            | Self::Friends { requester, .. }
+            | Self::FriendRequests { requester, .. }
+            | Self::FriendChallenged { requester, .. }
+            | Self::SearchName { requester, .. }
+            | Self::Failed { requester, .. } => Some(*requester),
+            Self::QuestionsLoaded { .. } => None,
+        }
+    }
+}
+
+/// Match results whose save is still queued. Saves run on the worker of the
+/// account while a login runs on the one of its credentials, so the login
+/// may load the rank from before the match. See `PlayerRecord::apply_rank`.
+#[derive(Clone, Default)]
+pub struct UnsavedRanks(Arc<Mutex<HashMap<i32, PlayerRank>>>);
+
+impl UnsavedRanks {
+    /// Taken before the login reads the account. A save missing from it has
+    /// already been written.
+    pub fn snapshot(&self) -> HashMap<i32, PlayerRank> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn insert(&self, rank: PlayerRank) {
+        self.0.lock().unwrap().insert(rank.id, rank);
+    }
+
+    /// Forgets `rank` once it is written, unless a newer one was queued.
+    fn remove(&self, rank: &PlayerRank) {
+        let mut ranks = self.0.lock().unwrap();
+        if ranks
+            .get(&rank.id)
+            .is_some_and(|x| x.games() <= rank.games())
+        {
+            ranks.remove(&rank.id);
+        }
+    }
+}
+
+/// Runs queries on worker threads so a slow query never blocks the ENet
+/// service loop. Each worker owns one storage handle and a queue of its own.
+///
+/// Jobs with the same key run on the same worker in the order they were
+/// queued. Jobs of a player use the account ID, so a write is never
+/// overtaken by a later read of the same account.
+pub struct DatabasePool {
+    jobs: Vec<Sender<Job>>,
+    /// Worker of the next unordered job
+    next_worker: Cell<usize>,
+    unsaved_ranks: UnsavedRanks,
+    events: Receiver<DatabaseEvent>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl DatabasePool {
+    pub fn new(storages: Vec<Box<dyn Storage>>) -> Self {
+        assert!(!storages.is_empty(), "No storage for the database workers");
+
+        let (event_sender, event_receiver) = mpsc::channel();
+
+        let (jobs, workers) = storages
+            .into_iter()
+            .enumerate()
+            .map(|(i, storage)| {
+                let (job_sender, job_receiver) = mpsc::channel::<Job>();
+                let events = event_sender.clone();
+
+                let worker = thread::Builder::new()
+                    .name(format!("database-{}", i))
+                    .spawn(move || Self::worker(storage, job_receiver, events))
+                    .expect("Cannot spawn database worker");
+
+                (job_sender, worker)
+            })
+            .unzip();
+
+        Self {
+            jobs,
+            next_worker: Cell::new(0),
+            unsaved_ranks: UnsavedRanks::default(),
+            events: event_receiver,
+            workers,
+        }
+    }
+
+    fn worker(mut storage: Box<dyn Storage>, jobs: Receiver<Job>, events: Sender<DatabaseEvent>) {
+        for job in jobs {
+            let storage = storage.as_mut();
+            match std::panic::catch_unwind(AssertUnwindSafe(|| job(storage))) {
+                Ok(Some(event)) => {
+                    if events.send(event).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(_) => println!("Database job panicked"),
+            }
+        }
+    }
+
+    /// Runs `job` after every job queued before it with the same `key`.
+    pub fn execute<K, F>(&self, key: K, job: F)
+    where
+        K: Hash,
+        F: FnOnce(&mut dyn Storage) -> Option<DatabaseEvent> + Send + 'static,
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        let worker = (hasher.finish() % self.jobs.len() as u64) as usize;
+        self.send(worker, Box::new(job));
+    }
+
+    /// Runs `job` on the next worker in turn. Only for jobs that may overtake
+    /// others, like reads that don't belong to the player asking.
+    pub fn execute_unordered<F>(&self, job: F)
+    where
+        F: FnOnce(&mut dyn Storage) -> Option<DatabaseEvent> + Send + 'static,
+    {
+        let worker = self.next_worker.get();
+        self.next_worker.set((worker + 1) % self.jobs.len());
+        self.send(worker, Box::new(job));
+    }
+
+    /// Queues the save of a match result, it stays in `unsaved_ranks` until
+    /// it is written.
+    pub fn save_rank(&self, rank: PlayerRank) {
+        self.unsaved_ranks.insert(rank);
+
+        let unsaved_ranks = self.unsaved_ranks.clone();
+        self.execute(rank.id, move |db| {
+            match db.save_rank(&rank) {
+                Ok(()) => unsaved_ranks.remove(&rank),
+                // Logins keep getting the rank from memory
+                Err(error) => println!("Save rank error: {}", error),
+            }
+
+            None
+        });
+    }
+
+    pub fn unsaved_ranks(&self) -> UnsavedRanks {
+        self.unsaved_ranks.clone()
+    }
+
+    fn send(&self, worker: usize, job: Job) {
+        let Some(jobs) = self.jobs.get(worker) else {
+            return;
+        };
+
+        if jobs.send(job).is_err() {
+            println!("Database workers are gone, dropping job");
+        }
+    }
+
+    /// Returns the next finished job without blocking.
+    pub fn poll(&self) -> Option<DatabaseEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+impl Drop for DatabasePool {
+    /// Lets the workers finish queued jobs, such as rank saves, before exit.
+    fn drop(&mut self) {
+        self.jobs.clear();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        database::{memory::MemoryDatabase, PlayerRecord},
+    };
+    use std::time::Duration;
+
+    fn pool(workers: usize) -> DatabasePool {
+        let storages = (0..workers)
+            .map(|_| {
+                let mut storage = MemoryDatabase::new(&Config::default()).unwrap();
+                storage.insert_new_player("rid").unwrap();
+                Box::new(storage) as Box<dyn Storage>
+            })
+            .collect();
+
+        DatabasePool::new(storages)
+    }
+
+    fn rank(rating: i32, win_count: i32) -> PlayerRank {
+        PlayerRank {
+            id: 1,
+            rating,
+            win_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn jobs_with_the_same_key_see_earlier_writes() {
+        // Every worker has storage of its own, a load on another worker than
+        // the save would miss it
+        let pool = pool(4);
+
+        let (sender, receiver) = mpsc::channel();
+        for rating in 1..=50 {
+            pool.execute_unordered(|db| {
+                db.get_leaderboard().unwrap();
+                None
+            });
+
+            pool.execute(1, move |db| {
+                let rank = PlayerRank {
+                    id: 1,
+                    rating,
+                    ..Default::default()
+                };
+                // Slow enough for the load to be picked up meanwhile
+                thread::sleep(Duration::from_millis(1));
+                db.save_rank(&rank).unwrap();
+                None
+            });
+
+            let sender = sender.clone();
+            pool.execute(1, move |db| {
+                let record = db.load_player_by_id(1).unwrap();
+                sender.send((rating, record.rating)).unwrap();
+                None
+            });
+        }
+
+        drop(pool);
+        drop(sender);
+
+        let loaded = receiver.iter().collect::<Vec<_>>();
+        assert_eq!(loaded.len(), 50);
+        for (saved, loaded) in loaded {
+            assert_eq!(saved, loaded);
+        }
+    }
+
+    #[test]
+    fn written_rank_is_no_longer_unsaved() {
+        let pool = pool(4);
+        pool.save_rank(rank(30, 1));
+
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(1, move |_| {
+            sender.send(()).unwrap();
+            None
+        });
+        receiver.recv().unwrap();
+
+        assert!(pool.unsaved_ranks().snapshot().is_empty());
+    }
+
+    #[test]
+    fn newer_unsaved_rank_is_kept() {
+        let unsaved_ranks = UnsavedRanks::default();
+        unsaved_ranks.insert(rank(30, 1));
+        unsaved_ranks.insert(rank(40, 2));
+
+        // The save of the first match finished
+        unsaved_ranks.remove(&rank(30, 1));
+        assert_eq!(unsaved_ranks.snapshot()[&1].rating, 40);
+
+        unsaved_ranks.remove(&rank(40, 2));
+        assert!(unsaved_ranks.snapshot().is_empty());
+    }
+
+    #[test]
+    fn only_a_newer_rank_is_applied() {
+        let mut record = PlayerRecord {
+            id: 1,
+            rating: 10,
+            win_count: 1,
+            ..Default::default()
+        };
+
+        // Loaded after the save, the record is as new as the rank
+        record.apply_rank(&rank(20, 1));
+        assert_eq!(record.rating, 10);
+
+        record.apply_rank(&rank(30, 2));
+        assert_eq!((record.rating, record.win_count), (30, 2));
+    }
+}
@@ -0,0 +1,448 @@
+use super::Storage;
+use crate::{
+    config::QuestionFormat,
+    question::{MAX_DIFFICULTY, MIN_DIFFICULTY},
+    state::GameQuestion,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs::File, path::Path};
+
+/// A question as it is written in import and export files. Files are meant to
+/// be shared between servers, so the ID is left out.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct QuestionRow {
+    question: String,
+    answer_option_1: String,
+    answer_option_2: String,
+    answer_option_3: String,
+    answer_option_4: String,
+    answer_index: i32,
+    #[serde(default = "default_category")]
+    category: String,
+    #[serde(default = "default_difficulty")]
+    difficulty: i32,
+    #[serde(default = "default_language")]
+    language: String,
+}
+
+/// Same defaults as the `QuestionLists` columns.
+fn default_category() -> String {
+    "general".to_string()
+}
+
+fn default_difficulty() -> i32 {
+    2
+}
+
+fn default_language() -> String {
+    "id".to_string()
+}
+
+impl QuestionRow {
+    fn options(&self) -> [&str; 4] {
+        [
+            &self.answer_option_1,
+            &self.answer_option_2,
+            &self.answer_option_3,
+            &self.answer_option_4,
+        ]
+    }
+
+    /// Everything wrong with the row, empty if it can be imported.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.question.trim().is_empty() {
+            problems.push("question is empty".to_string());
+        }
+
+        let options = self.options();
+        for (i, option) in options.iter().enumerate() {
+            if option.trim().is_empty() {
+                problems.push(format!("AnswerOption{} is empty", i + 1));
+            }
+        }
+
+        let unique = options
+            .iter()
+            .map(|x| x.trim().to_lowercase())
+            .filter(|x| !x.is_empty())
+            .collect::<HashSet<_>>();
+        if unique.len() != options.iter().filter(|x| !x.trim().is_empty()).count() {
+            problems.push("answer options are not unique".to_string());
+        }
+
+        if !(0..=3).contains(&self.answer_index) {
+            problems.push(format!("AnswerIndex {} is not 0 to 3", self.answer_index));
+        }
+
+        if !(MIN_DIFFICULTY..=MAX_DIFFICULTY).contains(&self.difficulty) {
+            problems.push(format!(
+                "Difficulty {} is not {} to {}",
+                self.difficulty, MIN_DIFFICULTY, MAX_DIFFICULTY
+            ));
+        }
+
+        if self.category.trim().is_empty() {
+            problems.push("Category is empty".to_string());
+        }
+
+        if self.language.trim().is_empty() {
+            problems.push("Language is empty".to_string());
+        }
+
+        problems
+    }
+}
+
+impl From<GameQuestion> for QuestionRow {
+    fn from(question: GameQuestion) -> Self {
+        Self {
+            question: question.question,
+            answer_option_1: question.answer_option_1,
+            answer_option_2: question.answer_option_2,
+            answer_option_3: question.answer_option_3,
+            answer_option_4: question.answer_option_4,
+            answer_index: question.answer_index,
+            category: question.category,
+            difficulty: question.difficulty,
+            language: question.language,
+        }
+    }
+}
+
+impl From<QuestionRow> for GameQuestion {
+    fn from(row: QuestionRow) -> Self {
+        Self {
+            id: 0,
+            question: row.question.trim().to_string(),
+            answer_option_1: row.answer_option_1.trim().to_string(),
+            answer_option_2: row.answer_option_2.trim().to_string(),
+            answer_option_3: row.answer_option_3.trim().to_string(),
+            answer_option_4: row.answer_option_4.trim().to_string(),
+            answer_index: row.answer_index,
+            category: row.category.trim().to_lowercase(),
+            difficulty: row.difficulty,
+            language: row.language.trim().to_lowercase(),
+        }
+    }
+}
+
+/// Questions are duplicates if their text only differs in case and spacing.
+fn normalize_question(question: &str) -> String {
+    question
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The format given on the command line, or else the one of the extension.
+fn resolve_format(path: &Path, format: Option<QuestionFormat>) -> anyhow::Result<QuestionFormat> {
+    if let Some(format) = format {
+        return Ok(format);
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+
+    match extension.as_deref() {
+        Some("csv") => Ok(QuestionFormat::Csv),
+        Some("json") => Ok(QuestionFormat::Json),
+        _ => anyhow::bail!(
+            "Cannot tell the format of {} from its extension, pass --format",
+            path.display()
+        ),
+    }
+}
+
+fn read_rows(path: &Path, format: QuestionFormat) -> anyhow::Result<Vec<QuestionRow>> {
+    let file = File::open(path)?;
+
+    match format {
+        QuestionFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(file);
+
+            reader
+                .deserialize()
+                .enumerate()
+                .map(|(i, row)| row.map_err(|e| anyhow::anyhow!("Row {}: {}", i + 1, e)))
+                .collect()
+        }
+        QuestionFormat::Json => Ok(serde_json::from_reader(file)?),
+    }
+}
+
+/// Validates the questions of `path` and, unless `dry_run` is set, adds them
+/// all to the question bank. Nothing is added if any question is invalid.
+pub fn import(
+    storage: &mut dyn Storage,
+    path: &Path,
+    format: Option<QuestionFormat>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let format = resolve_format(path, format)?;
+    let rows = read_rows(path, format)?;
+
+    let mut known = storage
+        .get_all_questions()?
+        .iter()
+        .map(|x| normalize_question(&x.question))
+        .collect::<HashSet<_>>();
+
+    let mut invalid = 0;
+    for (i, row) in rows.iter().enumerate() {
+        let mut problems = row.problems();
+
+        let question = normalize_question(&row.question);
+        if !question.is_empty() && !known.insert(question) {
+            problems.push("question already exists".to_string());
+        }
+
+        if !problems.is_empty() {
+            invalid += 1;
+            println!("Row {}: {}", i + 1, problems.join(", "));
+        }
+    }
+
+    println!(
+        "{} questions read, {} valid, {} invalid",
+        rows.len(),
+        rows.len() - invalid,
+        invalid
+    );
+
+    if invalid > 0 {
+        anyhow::bail!("Nothing imported, fix the invalid questions first");
+    }
+
+    if dry_run {
+        println!("Dry run, nothing imported");
+        return Ok(());
+    }
+
+    let questions = rows.into_iter().map(GameQuestion::from).collect::<Vec<_>>();
+    storage.insert_questions(&questions)?;
+    println!("Imported {} questions", questions.len());
+
+    Ok(())
+}
+
+/// Writes the whole question bank to `path`.
+pub fn export(
+    storage: &mut dyn Storage,
+    path: &Path,
+    format: Option<QuestionFormat>,
+) -> anyhow::Result<()> {
+    let format = resolve_format(path, format)?;
+    let rows = storage
+        .get_all_questions()?
+        .into_iter()
+        .map(QuestionRow::from)
+        .collect::<Vec<_>>();
+
+    let file = File::create(path)?;
+    match format {
+        QuestionFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            for row in &rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        QuestionFormat::Json => serde_json::to_writer_pretty(file, &rows)?,
+    }
+
+    println!("Exported {} questions to {}", rows.len(), path.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, database::memory::MemoryDatabase};
+    use std::{ops::Deref, path::PathBuf};
+
+    const HEADER: &str =
+        "Question,AnswerOption1,AnswerOption2,AnswerOption3,AnswerOption4,AnswerIndex";
+
+    fn row(question: &str, options: [&str; 4], answer_index: i32) -> QuestionRow {
+        QuestionRow {
+            question: question.to_string(),
+            answer_option_1: options[0].to_string(),
+            answer_option_2: options[1].to_string(),
+            answer_option_3: options[2].to_string(),
+            answer_option_4: options[3].to_string(),
+            answer_index,
+            category: default_category(),
+            difficulty: default_difficulty(),
+            language: default_language(),
+        }
+    }
+
+    /// A file in the temporary directory, unique to the test and removed
+    /// when dropped.
+    struct TempFile(PathBuf);
+
+    impl Deref for TempFile {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn file(name: &str, content: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("spc-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        TempFile(path)
+    }
+
+    fn database() -> MemoryDatabase {
+        MemoryDatabase::new(&Config::default()).unwrap()
+    }
+
+    fn count(db: &mut MemoryDatabase) -> usize {
+        db.get_all_questions().unwrap().len()
+    }
+
+    #[test]
+    fn valid_row_has_no_problems() {
+        let row = row("Ibu kota Jepang?", ["Tokyo", "Osaka", "Kyoto", "Nagoya"], 0);
+        assert!(row.problems().is_empty());
+    }
+
+    #[test]
+    fn invalid_rows_are_reported() {
+        let duplicate = row("Q", ["Tokyo", " tokyo ", "Kyoto", "Nagoya"], 0);
+        assert_eq!(duplicate.problems(), ["answer options are not unique"]);
+
+        let empty = row("Q", ["Tokyo", "Osaka", " ", "Nagoya"], 0);
+        assert_eq!(empty.problems(), ["AnswerOption3 is empty"]);
+
+        for answer_index in [-1, 4] {
+            let out_of_range = row("Q", ["A", "B", "C", "D"], answer_index);
+            assert_eq!(out_of_range.problems().len(), 1);
+        }
+
+        let mut hard = row("", ["A", "B", "C", "D"], 0);
+        hard.difficulty = MAX_DIFFICULTY + 1;
+        assert_eq!(hard.problems().len(), 2);
+    }
+
+    #[test]
+    fn csv_import_adds_every_question() {
+        let mut db = database();
+        let before = count(&mut db);
+
+        let path = file(
+            "import.csv",
+            &format!(
+                "{}\n\"Ibu kota Jepang, ya?\",Tokyo,Osaka,Kyoto,Nagoya,0\nQ2,A,B,C,D,3\n",
+                HEADER
+            ),
+        );
+        import(&mut db, &path, None, false).unwrap();
+
+        let questions = db.get_all_questions().unwrap();
+        assert_eq!(questions.len(), before + 2);
+        let added = &questions[before];
+        assert_eq!(added.question, "Ibu kota Jepang, ya?");
+        assert_eq!(added.category, "general");
+        assert_eq!(added.difficulty, 2);
+        assert_eq!(added.language, "id");
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let mut db = database();
+        let before = count(&mut db);
+
+        let path = file("dry-run.csv", &format!("{}\nQ,A,B,C,D,0\n", HEADER));
+        import(&mut db, &path, None, true).unwrap();
+
+        assert_eq!(count(&mut db), before);
+    }
+
+    #[test]
+    fn one_invalid_row_imports_nothing() {
+        let mut db = database();
+        let before = count(&mut db);
+
+        let path = file(
+            "invalid.csv",
+            &format!("{}\nQ1,A,B,C,D,0\nQ2,A,B,C,D,4\n", HEADER),
+        );
+        assert!(import(&mut db, &path, None, false).is_err());
+
+        assert_eq!(count(&mut db), before);
+    }
+
+    #[test]
+    fn duplicates_are_refused() {
+        let mut db = database();
+        let before = count(&mut db);
+
+        // Twice in the same file, differing only in case and spacing
+        let path = file(
+            "duplicate.csv",
+            &format!(
+                "{}\nSame question,A,B,C,D,0\n same  QUESTION ,A,B,C,D,0\n",
+                HEADER
+            ),
+        );
+        assert!(import(&mut db, &path, None, false).is_err());
+
+        // Already in the seed questions
+        let path = file(
+            "existing.csv",
+            &format!("{}\nnegara terkecil di dunia adalah?,A,B,C,D,0\n", HEADER),
+        );
+        assert!(import(&mut db, &path, None, false).is_err());
+
+        assert_eq!(count(&mut db), before);
+    }
+
+    #[test]
+    fn export_round_trip() {
+        for format in ["csv", "json"] {
+            let mut db = database();
+            let path = file(&format!("export.{}", format), "");
+            export(&mut db, &path, None).unwrap();
+
+            let exported = read_rows(&path, resolve_format(&path, None).unwrap()).unwrap();
+            let questions = db.get_all_questions().unwrap();
+            assert_eq!(exported.len(), questions.len());
+            assert_eq!(exported[0].question, questions[0].question);
+            assert_eq!(exported[0].category, questions[0].category);
+
+            // Everything in it is already known
+            assert!(import(&mut db, &path, None, true).is_err());
+        }
+    }
+
+    #[test]
+    fn format_comes_from_the_extension() {
+        let guess = |path: &str| resolve_format(Path::new(path), None).ok();
+
+        assert_eq!(guess("questions.CSV"), Some(QuestionFormat::Csv));
+        assert_eq!(guess("questions.json"), Some(QuestionFormat::Json));
+        assert_eq!(guess("questions.txt"), None);
+        assert_eq!(
+            resolve_format(Path::new("questions.txt"), Some(QuestionFormat::Json)).ok(),
+            Some(QuestionFormat::Json)
+        );
+    }
+}
@@ -0,0 +1,564 @@
+use super::{
+    migration::{self, AppliedMigration, Migration, MigrationConnection},
+    FriendInfo, FriendRequestInfo, LeaderboardInfo, PlayerRank, PlayerRecord, SessionToken,
+    Storage,
+};
+use crate::{
+    config::{Config, DatabaseBackend},
+    state::GameQuestion,
+};
+use anyhow::anyhow;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::{path::PathBuf, time::Duration};
+
+pub struct SqliteDatabase {
+    pub con: Connection,
+    pub empty_pfp_path: PathBuf,
+}
+
+impl SqliteDatabase {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let mut database = Self::open(config)?;
+        migration::run(
+            &mut database.con,
+            &config.server.migrations_dir(DatabaseBackend::Sqlite),
+        )?;
+
+        Ok(database)
+    }
+
+    /// Opens another connection without running migrations.
+    pub fn open(config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            con: Self::connect(config)?,
+            empty_pfp_path: config.server.empty_profile_picture(),
+        })
+    }
+
+    pub fn connect(config: &Config) -> anyhow::Result<Connection> {
+        let con = Connection::open(&config.database.path)?;
+        con.pragma_update(None, "foreign_keys", true)?;
+        // Other workers may hold the write lock
+        con.busy_timeout(Duration::from_secs(config.database.connect_timeout_secs))?;
+
+        Ok(con)
+    }
+
+    pub fn load_player_from_row(row: &Row) -> rusqlite::Result<PlayerRecord> {
+        Ok(PlayerRecord {
+            id: row.get(0)?,
+            rid: row.get(1)?,
+            name: row.get(2)?,
+            rating: row.get(3)?,
+            win_count: row.get(4)?,
+            lose_count: row.get(5)?,
+            pfp_blob: row.get(6)?,
+            pfp_ext: row.get(7)?,
+            username: row.get(8)?,
+            rating_deviation: row.get(10)?,
+            rating_volatility: row.get(11)?,
+            draw_count: row.get(12)?,
+        })
+    }
+
+    pub fn load_session_token_from_row(row: &Row) -> rusqlite::Result<SessionToken> {
+        Ok(SessionToken {
+            token_hash: row.get(0)?,
+            player_id: row.get(1)?,
+            expires_at: row.get(2)?,
+        })
+    }
+
+    pub fn load_question_from_row(row: &Row) -> rusqlite::Result<GameQuestion> {
+        Ok(GameQuestion {
+            id: row.get(0)?,
+            question: row.get(1)?,
+            answer_option_1: row.get(2)?,
+            answer_option_2: row.get(3)?,
+            answer_option_3: row.get(4)?,
+            answer_option_4: row.get(5)?,
+            answer_index: row.get(6)?,
+            category: row.get(7)?,
+            difficulty: row.get(8)?,
+            language: row.get(9)?,
+        })
+    }
+
+    pub fn load_leaderboard_from_row(row: &Row) -> rusqlite::Result<LeaderboardInfo> {
+        Ok(LeaderboardInfo {
+            name: row.get(2)?,
+            rating: row.get(3)?,
+            win: row.get(4)?,
+            lose: row.get(5)?,
+            draw: row.get(12)?,
+            pfp: row.get(6)?,
+            pfp_ext: row.get(7)?,
+        })
+    }
+}
+
+impl Storage for SqliteDatabase {
+    fn save_player_name(&mut self, id: i32, name: &str) -> anyhow::Result<()> {
+        self.con.execute(
+            "UPDATE Players SET Name = ? WHERE ID = ?;",
+            params![name, id],
+        )?;
+
+        Ok(())
+    }
+
+    fn insert_new_player(&mut self, rid: &str) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO Players (
+                RID,
+                ProfilePicture
+            ) VALUES (
+                ?,
+                ?
+            );
+        ";
+
+        let data = std::fs::read(&self.empty_pfp_path)?;
+        self.con.execute(QUERY, params![rid, data])?;
+
+        let id = self.con.last_insert_rowid() as i32;
+        self.save_player_name(id, &format!("GUEST_{}", id))
+    }
+
+    fn is_player_exist_by_rid(&mut self, rid: &str) -> anyhow::Result<bool> {
+        const QUERY: &str = "SELECT EXISTS(SELECT 1 FROM Players WHERE RID = ? LIMIT 1);";
+
+        Ok(self.con.query_row(QUERY, params![rid], |row| row.get(0))?)
+    }
+
+    fn is_player_exist_by_name(&mut self, name: &str) -> anyhow::Result<bool> {
+        const QUERY: &str = "SELECT EXISTS(SELECT 1 FROM Players WHERE Name = ? LIMIT 1);";
+
+        Ok(self.con.query_row(QUERY, params![name], |row| row.get(0))?)
+    }
+
+    fn load_player_by_rid(&mut self, rid: &str) -> anyhow::Result<PlayerRecord> {
+        const QUERY: &str = "SELECT * FROM Players WHERE RID = ? LIMIT 1;";
+
+        self.con
+            .query_row(QUERY, params![rid], Self::load_player_from_row)
+            .optional()?
+            .ok_or_else(|| anyhow!("Cannot find player with RID of {}", rid))
+    }
+
+    fn load_player_by_id(&mut self, id: i32) -> anyhow::Result<PlayerRecord> {
+        const QUERY: &str = "SELECT * FROM Players WHERE ID = ? LIMIT 1;";
+
+        self.con
+            .query_row(QUERY, params![id], Self::load_player_from_row)
+            .optional()?
+            .ok_or_else(|| anyhow!("Cannot find player with ID of {}", id))
+    }
+
+    fn save_rank(&mut self, rank: &PlayerRank) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            UPDATE Players SET
+                Rating = ?,
+                RatingDeviation = ?,
+                RatingVolatility = ?,
+                WinCount = ?,
+                LoseCount = ?,
+                DrawCount = ?
+            WHERE ID = ?;";
+
+        self.con.execute(
+            QUERY,
+            params![
+                rank.rating,
+                rank.rating_deviation,
+                rank.rating_volatility,
+                rank.win_count,
+                rank.lose_count,
+                rank.draw_count,
+                rank.id
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn get_all_questions(&mut self) -> anyhow::Result<Vec<GameQuestion>> {
+        let mut statement = self.con.prepare("SELECT * FROM QuestionLists;")?;
+        let questions = statement
+            .query_map([], Self::load_question_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(questions)
+    }
+
+    fn insert_questions(&mut self, questions: &[GameQuestion]) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO QuestionLists (
+                Question,
+                AnswerOption1,
+                AnswerOption2,
+                AnswerOption3,
+                AnswerOption4,
+                AnswerIndex,
+                Category,
+                Difficulty,
+                Language
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);";
+
+        let tx = self.con.transaction()?;
+        {
+            let mut statement = tx.prepare(QUERY)?;
+            for x in questions {
+                statement.execute(params![
+                    x.question,
+                    x.answer_option_1,
+                    x.answer_option_2,
+                    x.answer_option_3,
+                    x.answer_option_4,
+                    x.answer_index,
+                    x.category,
+                    x.difficulty,
+                    x.language,
+                ])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn get_leaderboard(&mut self) -> anyhow::Result<Vec<LeaderboardInfo>> {
+        let mut statement = self
+            .con
+            .prepare("SELECT * FROM Players ORDER BY Rating DESC LIMIT 10;")?;
+        let leaderboard = statement
+            .query_map([], Self::load_leaderboard_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(leaderboard)
+    }
+
+    fn get_pfp(&mut self, id: i32) -> anyhow::Result<(Vec<u8>, String)> {
+        const QUERY: &str = "
+            SELECT ProfilePicture, ProfilePictureExtension
+            FROM Players
+            WHERE ID = ?
+            LIMIT 1;
+        ";
+
+        self.con
+            .query_row(QUERY, params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?
+            .ok_or_else(|| anyhow!("Cannot find pfp with ID of {}", id))
+    }
+
+    fn get_pfp_by_name(&mut self, name: &str) -> anyhow::Result<(Vec<u8>, String)> {
+        const QUERY: &str = "
+            SELECT ProfilePicture, ProfilePictureExtension
+            FROM Players
+            WHERE Name = ?
+            LIMIT 1;
+        ";
+
+        self.con
+            .query_row(QUERY, params![name], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?
+            .ok_or_else(|| anyhow!("Cannot find pfp with Name of {}", name))
+    }
+
+    fn get_friends(&mut self, id: i32) -> anyhow::Result<Vec<FriendInfo>> {
+        const QUERY: &str = "
+            SELECT Players.ID, Players.Name
+            FROM FriendLists
+            INNER JOIN Players ON FriendLists.FriendID = Players.ID
+            WHERE FriendLists.PlayerID = ?
+        ";
+
+        let mut statement = self.con.prepare(QUERY)?;
+        let friends = statement
+            .query_map(params![id], |row| {
+                Ok(FriendInfo {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    pfp_ext: String::new(),
+                    pfp: Vec::new(),
+                    online: false,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(friends)
+    }
+
+    fn add_friend(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO FriendLists (PlayerID, FriendID)
+            VALUES (?, ?)
+        ";
+
+        self.con.execute(QUERY, params![id, target])?;
+
+        Ok(())
+    }
+
+    fn get_friend_requests(&mut self, id: i32) -> anyhow::Result<Vec<FriendRequestInfo>> {
+        const QUERY: &str = "
+            SELECT Players.ID, Players.Name
+            FROM FriendRequests
+            INNER JOIN Players ON FriendRequests.FriendID = Players.ID
+            WHERE FriendRequests.PlayerID = ?
+        ";
+
+        let mut statement = self.con.prepare(QUERY)?;
+        let friend_requests = statement
+            .query_map(params![id], |row| {
+                Ok(FriendRequestInfo {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    pfp_ext: String::new(),
+                    pfp: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(friend_requests)
+    }
+
+    fn add_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO FriendRequests (PlayerID, FriendID, DateRequested)
+            VALUES (?, ?, DATE('now'))
+        ";
+
+        self.con.execute(QUERY, params![id, target])?;
+
+        Ok(())
+    }
+
+    fn remove_friend_request(&mut self, id: i32, target: i32) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            DELETE FROM FriendRequests
+            WHERE PlayerID = ? AND FriendID = ?
+        ";
+
+        self.con.execute(QUERY, params![id, target])?;
+
+        Ok(())
+    }
+
+    fn update_profile_picture(
+        &mut self,
+        id: i32,
+        pfp_blob: &[u8],
+        extension: &str,
+    ) -> anyhow::Result<()> {
+        self.con.execute(
+            "UPDATE Players SET ProfilePicture = ?, ProfilePictureExtension = ? WHERE ID = ?;",
+            params![pfp_blob, extension, id],
+        )?;
+
+        Ok(())
+    }
+
+    fn is_username_taken(&mut self, username: &str) -> anyhow::Result<bool> {
+        const QUERY: &str = "SELECT EXISTS(SELECT 1 FROM Players WHERE Username = ? LIMIT 1);";
+
+        Ok(self
+            .con
+            .query_row(QUERY, params![username], |row| row.get(0))?)
+    }
+
+    fn load_credentials(&mut self, username: &str) -> anyhow::Result<Option<(i32, String)>> {
+        const QUERY: &str = "
+            SELECT ID, PasswordHash
+            FROM Players
+            WHERE Username = ? AND PasswordHash IS NOT NULL
+            LIMIT 1;
+        ";
+
+        Ok(self
+            .con
+            .query_row(QUERY, params![username], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?)
+    }
+
+    fn save_credentials(
+        &mut self,
+        id: i32,
+        username: &str,
+        password_hash: &str,
+    ) -> anyhow::Result<()> {
+        self.con.execute(
+            "UPDATE Players SET Username = ?, PasswordHash = ? WHERE ID = ?;",
+            params![username, password_hash, id],
+        )?;
+
+        Ok(())
+    }
+
+    fn insert_session_token(&mut self, token: &SessionToken) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            INSERT INTO SessionTokens (TokenHash, PlayerID, ExpiresAt)
+            VALUES (?, ?, ?)
+        ";
+
+        self.con.execute(
+            QUERY,
+            params![token.token_hash, token.player_id, token.expires_at],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_session_token(&mut self, token_hash: &str) -> anyhow::Result<Option<SessionToken>> {
+        const QUERY: &str = "
+            SELECT TokenHash, PlayerID, ExpiresAt
+            FROM SessionTokens
+            WHERE TokenHash = ?
+            LIMIT 1;
+        ";
+
+        Ok(self
+            .con
+            .query_row(
+                QUERY,
+                params![token_hash],
+                Self::load_session_token_from_row,
+            )
+            .optional()?)
+    }
+
+    fn remove_session_token(&mut self, token_hash: &str) -> anyhow::Result<()> {
+        self.con.execute(
+            "DELETE FROM SessionTokens WHERE TokenHash = ?;",
+            params![token_hash],
+        )?;
+
+        Ok(())
+    }
+
+    fn remove_session_tokens(&mut self, player_id: i32) -> anyhow::Result<usize> {
+        Ok(self.con.execute(
+            "DELETE FROM SessionTokens WHERE PlayerID = ?;",
+            params![player_id],
+        )?)
+    }
+
+    fn remove_expired_session_tokens(&mut self, player_id: i32, now: i64) -> anyhow::Result<usize> {
+        Ok(self.con.execute(
+            "DELETE FROM SessionTokens WHERE PlayerID = ? AND ExpiresAt <= ?;",
+            params![player_id, now],
+        )?)
+    }
+}
+
+impl MigrationConnection for Connection {
+    fn ensure_migrations_table(&mut self) -> anyhow::Result<()> {
+        const QUERY: &str = "
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                Version INTEGER NOT NULL PRIMARY KEY,
+                Name VARCHAR(255) NOT NULL,
+                Checksum CHAR(64) NOT NULL,
+                AppliedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+        ";
+
+        self.execute_batch(QUERY)?;
+
+        Ok(())
+    }
+
+    fn applied_migrations(&mut self) -> anyhow::Result<Vec<AppliedMigration>> {
+        let mut statement = self
+            .prepare("SELECT Version, Name, Checksum FROM schema_migrations ORDER BY Version;")?;
+        let applied = statement
+            .query_map([], |row| {
+                Ok(AppliedMigration {
+                    version: row.get(0)?,
+                    name: row.get(1)?,
+                    checksum: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(applied)
+    }
+
+    fn table_exists(&mut self, table: &str) -> anyhow::Result<bool> {
+        let count: i64 = self.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?;",
+            params![table],
+            |row| row.get(0),
+        )?;
+
+        Ok(count > 0)
+    }
+
+    fn mark_applied(&mut self, migration: &Migration) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT INTO schema_migrations (Version, Name, Checksum) VALUES (?, ?, ?);",
+            params![migration.version, migration.name, migration.checksum],
+        )?;
+
+        Ok(())
+    }
+
+    fn apply(&mut self, migration: &Migration) -> anyhow::Result<()> {
+        let tx = self.transaction()?;
+        tx.execute_batch(&migration.up)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (Version, Name, Checksum) VALUES (?, ?, ?);",
+            params![migration.version, migration.name, migration.checksum],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn revert(&mut self, migration: &Migration, down: &str) -> anyhow::Result<()> {
+        let tx = self.transaction()?;
+        tx.execute_batch(down)?;
+        tx.execute(
+            "DELETE FROM schema_migrations WHERE Version = ?;",
+            params![migration.version],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn legacy_database_keeps_its_questions() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("runtime/migrations/sqlite");
+        let mut con = Connection::open_in_memory().unwrap();
+
+        // What the runner did before `schema_migrations` existed.
+        for file in [
+            "0-create-player.sql",
+            "1-create-question.sql",
+            "2-create-friend.sql",
+        ] {
+            con.execute_batch(&std::fs::read_to_string(dir.join(file)).unwrap())
+                .unwrap();
+        }
+
+        migration::run(&mut con, &dir).unwrap();
+        migration::run(&mut con, &dir).unwrap();
+
+        let questions: i64 = con
+            .query_row("SELECT COUNT(*) FROM QuestionLists;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(questions, 20);
+
+        let applied = con.applied_migrations().unwrap();
+        assert_eq!(applied.len(), migration::load(&dir).unwrap().len());
+    }
+}
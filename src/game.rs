@@ -1,37 +1,304 @@
-use crate::{player::Player, state::GameQuestion};
-use std::{cell::RefCell, collections::HashSet, rc::Rc, time::Instant};
+use crate::{config::MatchRules, player::Player, question::QuestionFilter, state::GameQuestion};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// Most players a free-for-all match can hold.
+pub const MAX_PLAYERS: usize = 8;
+
+/// Identifies a game for as long as the server runs, IDs are never reused.
+pub type GameId = u64;
+
+/// Where a match is in its lifecycle, sent to the client as an i32. Every
+/// phase ends at the game's deadline except `Finished`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Phase {
+    /// Before the first question
+    Countdown = 0,
+    /// Players may answer
+    QuestionOpen = 1,
+    /// The correct answer is shown
+    Reveal = 2,
+    /// Between the reveal and the next question
+    Intermission = 3,
+    Finished = 4,
+}
 
 pub struct Game {
-    pub p1: Rc<RefCell<Player>>,
-    pub p2: Rc<RefCell<Player>>,
+    pub id: GameId,
+    pub players: Vec<Rc<RefCell<Player>>>,
 
     pub question: GameQuestion,
 
-    pub stage: i32,
-    pub started: bool,
+    /// The question being played, 0 during the countdown
+    pub round: i32,
     pub competitive: bool,
+    pub rules: MatchRules,
+    pub question_filter: QuestionFilter,
 
-    pub start_timer: Instant,
-    pub stage_timer: Instant,
+    pub phase: Phase,
+    pub phase_started: Instant,
+    pub deadline: Instant,
 
-    pub previous_questions: HashSet<usize>,
+    /// IDs of the questions already asked
+    pub previous_questions: HashSet<i32>,
+}
+
+/// A participant's place on the scoreboard.
+#[derive(Debug, Clone)]
+pub struct Standing {
+    pub id: i32,
+    pub name: String,
+    pub points: i32,
+    /// Starts at 1, players with the same points share a placement
+    pub placement: i32,
 }
 
 impl Game {
-    pub fn new(p1: Rc<RefCell<Player>>, p2: Rc<RefCell<Player>>, competitive: bool) -> Self {
+    pub fn new(
+        id: GameId,
+        players: Vec<Rc<RefCell<Player>>>,
+        competitive: bool,
+        rules: MatchRules,
+        question_filter: QuestionFilter,
+    ) -> Self {
+        let now = Instant::now();
+        let deadline = now + Duration::from_secs(rules.countdown_secs);
+
         Self {
-            p1,
-            p2,
+            id,
+            players,
             competitive,
+            rules,
+            question_filter,
 
-            stage: 0,
-            started: false,
+            round: 0,
             question: GameQuestion::default(),
 
-            start_timer: Instant::now(),
-            stage_timer: Instant::now(),
+            phase: Phase::Countdown,
+            phase_started: now,
+            deadline,
 
             previous_questions: HashSet::new(),
         }
     }
+
+    /// Every participant except `player`.
+    pub fn opponents<'a>(
+        &'a self,
+        player: &'a Rc<RefCell<Player>>,
+    ) -> impl Iterator<Item = &'a Rc<RefCell<Player>>> {
+        self.players.iter().filter(|x| !Rc::ptr_eq(x, player))
+    }
+
+    /// The phase that follows the current one once its deadline passes.
+    pub fn next_phase(&self) -> Phase {
+        match self.phase {
+            Phase::Countdown | Phase::Intermission => Phase::QuestionOpen,
+            Phase::QuestionOpen => Phase::Reveal,
+            Phase::Reveal if self.round >= self.rules.rounds => Phase::Finished,
+            Phase::Reveal => Phase::Intermission,
+            Phase::Finished => Phase::Finished,
+        }
+    }
+
+    /// Moves to `phase` and sets its deadline from the rules.
+    pub fn enter(&mut self, phase: Phase) {
+        let secs = match phase {
+            Phase::Countdown => self.rules.countdown_secs,
+            Phase::QuestionOpen => self.rules.question_secs,
+            Phase::Reveal => self.rules.reveal_secs,
+            Phase::Intermission => self.rules.intermission_secs,
+            Phase::Finished => 0,
+        };
+
+        self.phase = phase;
+        self.phase_started = Instant::now();
+        self.deadline = self.phase_started + Duration::from_secs(secs);
+    }
+
+    /// Ends the open question before its deadline once nobody is left to
+    /// answer, the reveal starts on the next tick.
+    pub fn close_question(&mut self) {
+        if self.phase == Phase::QuestionOpen {
+            self.deadline = Instant::now();
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Hands the slot of `old` over to another connection of the same player.
+    pub fn replace(&mut self, old: &Rc<RefCell<Player>>, new: Rc<RefCell<Player>>) {
+        if let Some(slot) = self.players.iter_mut().find(|x| Rc::ptr_eq(x, old)) {
+            *slot = new;
+        }
+    }
+}
+
+/// The scoreboard of `players`, in the same order. Players who forfeited
+/// are placed below everyone else.
+pub fn standings(players: &[&Player]) -> Vec<Standing> {
+    let key = |x: &Player| (x.forfeited, -x.points);
+
+    players
+        .iter()
+        .map(|player| Standing {
+            id: player.id,
+            name: player.name.clone(),
+            points: player.points,
+            placement: 1 + players.iter().filter(|x| key(x) < key(player)).count() as i32,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::RulesConfig, player::tests::player};
+
+    fn game(rounds: i32) -> Game {
+        let rules = MatchRules {
+            rounds,
+            countdown_secs: 3,
+            question_secs: 10,
+            reveal_secs: 2,
+            intermission_secs: 4,
+            ..RulesConfig::default().quick
+        };
+
+        Game::new(1, Vec::new(), false, rules, QuestionFilter::default())
+    }
+
+    /// Enters the next phase like `poll_game` and returns it with its length
+    /// in seconds.
+    fn step(game: &mut Game) -> (Phase, u64) {
+        let phase = game.next_phase();
+        if phase == Phase::QuestionOpen {
+            game.round += 1;
+        }
+
+        game.enter(phase);
+        (phase, (game.deadline - game.phase_started).as_secs())
+    }
+
+    #[test]
+    fn phases_follow_the_rules() {
+        let mut game = game(2);
+        assert_eq!(game.phase, Phase::Countdown);
+        assert_eq!((game.deadline - game.phase_started).as_secs(), 3);
+        assert!(!game.is_expired());
+
+        let phases = (0..7).map(|_| step(&mut game)).collect::<Vec<_>>();
+        assert_eq!(
+            phases,
+            [
+                (Phase::QuestionOpen, 10),
+                (Phase::Reveal, 2),
+                (Phase::Intermission, 4),
+                (Phase::QuestionOpen, 10),
+                (Phase::Reveal, 2),
+                (Phase::Finished, 0),
+                (Phase::Finished, 0),
+            ]
+        );
+        assert_eq!(game.round, 2);
+        assert!(game.is_expired());
+    }
+
+    #[test]
+    fn single_round_skips_the_intermission() {
+        let mut game = game(1);
+
+        let phases = (0..3).map(|_| step(&mut game).0).collect::<Vec<_>>();
+        assert_eq!(
+            phases,
+            [Phase::QuestionOpen, Phase::Reveal, Phase::Finished]
+        );
+    }
+
+    #[test]
+    fn question_closes_early() {
+        let mut game = game(2);
+
+        // Only an open question can close
+        game.close_question();
+        assert!(!game.is_expired());
+
+        step(&mut game);
+        assert!(!game.is_expired());
+
+        game.close_question();
+        assert!(game.is_expired());
+        assert_eq!(game.next_phase(), Phase::Reveal);
+
+        // The reveal still takes its time
+        step(&mut game);
+        assert!(!game.is_expired());
+    }
+
+    fn placements(players: &[Player]) -> Vec<i32> {
+        standings(&players.iter().collect::<Vec<_>>())
+            .iter()
+            .map(|x| x.placement)
+            .collect()
+    }
+
+    #[test]
+    fn tied_points_share_first_place() {
+        let players = [player(1, "A", 20), player(2, "B", 20)];
+        assert_eq!(placements(&players), [1, 1]);
+
+        let players = [player(1, "A", 20), player(2, "B", 30)];
+        assert_eq!(placements(&players), [2, 1]);
+    }
+
+    #[test]
+    fn players_with_the_same_points_share_a_placement() {
+        let players = [
+            player(1, "A", 10),
+            player(2, "B", 40),
+            player(3, "C", 10),
+            player(4, "D", 25),
+            player(5, "E", 0),
+        ];
+
+        // Both with 10 points come after the two ahead of them
+        assert_eq!(placements(&players), [3, 1, 3, 2, 5]);
+
+        let standing = &standings(&players.iter().collect::<Vec<_>>())[1];
+        assert_eq!((standing.id, standing.name.as_str()), (2, "B"));
+        assert_eq!(standing.points, 40);
+    }
+
+    #[test]
+    fn forfeited_players_are_ranked_last() {
+        let mut players = [
+            player(1, "A", 50),
+            player(2, "B", 10),
+            player(3, "C", 30),
+            player(4, "D", 0),
+        ];
+        players[0].forfeited = true;
+        players[2].forfeited = true;
+
+        // Forfeiting keeps the points, but only ranks against the others who
+        // forfeited
+        assert_eq!(placements(&players), [3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn everyone_forfeiting_ranks_by_points() {
+        let mut players = [player(1, "A", 10), player(2, "B", 30), player(3, "C", 10)];
+        for player in players.iter_mut() {
+            player.forfeited = true;
+        }
+
+        assert_eq!(placements(&players), [2, 1, 2]);
+    }
 }
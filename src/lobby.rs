@@ -0,0 +1,254 @@
+use crate::{config::MatchLength, question::QuestionFilter};
+use enet::PeerID;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// Letters and digits that cannot be mistaken for each other when read out.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 6;
+
+/// A private match waiting for its players, it starts once `capacity` is
+/// reached. Friend challenges are two player lobbies only the invited player
+/// may join.
+#[derive(Debug, Clone)]
+pub struct Lobby {
+    pub code: String,
+    pub host: PeerID,
+    pub host_id: i32,
+    pub length: MatchLength,
+    /// Player ID of the challenged friend
+    pub invited: Option<i32>,
+    pub capacity: usize,
+    pub filter: QuestionFilter,
+    /// Everyone who joined so far, without the host
+    pub guests: Vec<(PeerID, i32)>,
+}
+
+impl Lobby {
+    /// Host and guests.
+    pub fn members(&self) -> impl Iterator<Item = PeerID> + '_ {
+        [self.host]
+            .into_iter()
+            .chain(self.guests.iter().map(|(peer_id, _)| *peer_id))
+    }
+
+    pub fn is_member(&self, id: i32) -> bool {
+        self.host_id == id || self.guests.iter().any(|(_, x)| *x == id)
+    }
+
+    /// Seats still open, whoever takes the last one starts the match.
+    pub fn free_seats(&self) -> usize {
+        self.capacity.saturating_sub(1 + self.guests.len())
+    }
+}
+
+#[derive(Default)]
+pub struct Lobbies {
+    lobbies: HashMap<String, Lobby>,
+}
+
+impl Lobbies {
+    /// Opens a lobby hosted by `host` and returns its join code.
+    pub fn create(
+        &mut self,
+        host: PeerID,
+        host_id: i32,
+        length: MatchLength,
+        invited: Option<i32>,
+        capacity: usize,
+        filter: QuestionFilter,
+    ) -> String {
+        let mut rng = rand::thread_rng();
+        let code = loop {
+            let code: String = (0..CODE_LENGTH)
+                .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+                .collect();
+
+            if !self.lobbies.contains_key(&code) {
+                break code;
+            }
+        };
+
+        self.lobbies.insert(
+            code.clone(),
+            Lobby {
+                code: code.clone(),
+                host,
+                host_id,
+                length,
+                invited,
+                capacity,
+                filter,
+                guests: Vec::new(),
+            },
+        );
+
+        code
+    }
+
+    pub fn get(&self, code: &str) -> Option<&Lobby> {
+        self.lobbies.get(&code.to_ascii_uppercase())
+    }
+
+    pub fn get_mut(&mut self, code: &str) -> Option<&mut Lobby> {
+        self.lobbies.get_mut(&code.to_ascii_uppercase())
+    }
+
+    pub fn remove(&mut self, code: &str) -> Option<Lobby> {
+        self.lobbies.remove(&code.to_ascii_uppercase())
+    }
+
+    /// Closes the lobby hosted by `host`, if any.
+    pub fn remove_by_host(&mut self, host: PeerID) -> Option<Lobby> {
+        let code = self.lobbies.values().find(|x| x.host == host)?.code.clone();
+        self.lobbies.remove(&code)
+    }
+
+    /// Takes a guest out of its lobby, returns the lobby it left.
+    pub fn remove_guest(&mut self, guest: PeerID) -> Option<&Lobby> {
+        let lobby = self
+            .lobbies
+            .values_mut()
+            .find(|x| x.guests.iter().any(|(peer_id, _)| *peer_id == guest))?;
+
+        lobby.guests.retain(|(peer_id, _)| *peer_id != guest);
+        Some(lobby)
+    }
+
+    /// Hands a lobby spot over to another connection of the same player.
+    pub fn replace_member(&mut self, old: PeerID, new: PeerID) -> Option<&Lobby> {
+        let lobby = self
+            .lobbies
+            .values_mut()
+            .find(|x| x.members().any(|peer_id| peer_id == old))?;
+
+        if lobby.host == old {
+            lobby.host = new;
+        }
+
+        for (peer_id, _) in lobby.guests.iter_mut() {
+            if *peer_id == old {
+                *peer_id = new;
+            }
+        }
+
+        Some(lobby)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::tests::peer_id;
+    use std::collections::HashSet;
+
+    const HOST_ID: i32 = 1;
+
+    /// A lobby hosted by player 1.
+    fn lobby(lobbies: &mut Lobbies, capacity: usize) -> String {
+        lobbies.create(
+            peer_id(HOST_ID as usize),
+            HOST_ID,
+            MatchLength::Standard,
+            None,
+            capacity,
+            QuestionFilter::default(),
+        )
+    }
+
+    fn join(lobbies: &mut Lobbies, code: &str, id: i32) {
+        let lobby = lobbies.get_mut(code).unwrap();
+        lobby.guests.push((peer_id(id as usize), id));
+    }
+
+    #[test]
+    fn codes_are_unique_and_readable() {
+        let mut lobbies = Lobbies::default();
+        let codes = (0..100)
+            .map(|_| lobby(&mut lobbies, 2))
+            .collect::<HashSet<_>>();
+
+        assert_eq!(codes.len(), 100);
+        for code in codes {
+            assert_eq!(code.len(), CODE_LENGTH);
+            assert!(code.bytes().all(|x| CODE_ALPHABET.contains(&x)));
+            assert!(!code.contains(['0', 'O', '1', 'I']));
+        }
+    }
+
+    #[test]
+    fn codes_are_case_insensitive() {
+        let mut lobbies = Lobbies::default();
+        let code = lobby(&mut lobbies, 2);
+        let lowercase = code.to_ascii_lowercase();
+
+        assert_eq!(lobbies.get(&lowercase).unwrap().code, code);
+        assert!(lobbies.get_mut(&lowercase).is_some());
+        assert!(lobbies.get("").is_none());
+
+        assert!(lobbies.remove(&lowercase).is_some());
+        assert!(lobbies.get(&code).is_none());
+    }
+
+    #[test]
+    fn last_free_seat_starts_the_match() {
+        let mut lobbies = Lobbies::default();
+
+        let code = lobby(&mut lobbies, 2);
+        assert_eq!(lobbies.get(&code).unwrap().free_seats(), 1);
+
+        let code = lobby(&mut lobbies, 4);
+        assert_eq!(lobbies.get(&code).unwrap().free_seats(), 3);
+
+        join(&mut lobbies, &code, 2);
+        join(&mut lobbies, &code, 3);
+        let lobby = lobbies.get(&code).unwrap();
+        assert_eq!(lobby.free_seats(), 1);
+        assert_eq!(
+            lobby.members().collect::<Vec<_>>(),
+            [peer_id(1), peer_id(2), peer_id(3)]
+        );
+    }
+
+    #[test]
+    fn host_and_guests_are_members() {
+        let mut lobbies = Lobbies::default();
+        let code = lobby(&mut lobbies, 3);
+        join(&mut lobbies, &code, 2);
+
+        let lobby = lobbies.get(&code).unwrap();
+        assert!(lobby.is_member(HOST_ID));
+        assert!(lobby.is_member(2));
+        assert!(!lobby.is_member(3));
+    }
+
+    #[test]
+    fn leaving_guest_keeps_the_lobby_open() {
+        let mut lobbies = Lobbies::default();
+        let code = lobby(&mut lobbies, 4);
+        join(&mut lobbies, &code, 2);
+        join(&mut lobbies, &code, 3);
+
+        let lobby = lobbies.remove_guest(peer_id(2)).unwrap();
+        assert_eq!(lobby.guests, [(peer_id(3), 3)]);
+        assert!(lobbies.remove_by_host(peer_id(2)).is_none());
+        assert!(lobbies.get(&code).is_some());
+    }
+
+    #[test]
+    fn leaving_host_closes_the_lobby() {
+        let mut lobbies = Lobbies::default();
+        let code = lobby(&mut lobbies, 3);
+        join(&mut lobbies, &code, 2);
+
+        // The host is no guest of its own lobby
+        assert!(lobbies.remove_guest(peer_id(1)).is_none());
+
+        let lobby = lobbies.remove_by_host(peer_id(1)).unwrap();
+        assert_eq!(lobby.code, code);
+        // Left to be told the lobby closed
+        assert_eq!(lobby.guests, [(peer_id(2), 2)]);
+        assert!(lobbies.get(&code).is_none());
+        assert!(lobbies.remove_guest(peer_id(2)).is_none());
+    }
+}
@@ -1,18 +1,29 @@
+pub mod auth;
+pub mod config;
 pub mod database;
 pub mod game;
+pub mod lobby;
+pub mod matchmaking;
 pub mod player;
 pub mod protocol;
+pub mod question;
+pub mod rating;
 pub mod state;
 
-use bson::Document;
-use enet::{
-    Address, BandwidthLimit, ChannelLimit, Enet, EventKind, Host, Packet, PacketMode, Peer,
-};
-use player::Player;
-use rand::Rng;
-use state::State;
+use auth::InvalidCredentials;
+use clap::Parser;
+use config::{Cli, Command, Config, MatchLength};
+use database::pool::{DatabaseEvent, DatabasePool};
+use enet::{Address, BandwidthLimit, ChannelLimit, Enet, EventKind, Host, Packet, PacketMode};
+use game::{Game, Phase, MAX_PLAYERS};
+use matchmaking::QueueMode;
+use player::{Player, SessionState};
+use protocol::{ErrorCode, GameEndReason, GameOutcome, PacketError, Request};
+use question::QuestionFilter;
+use state::{GameQuestion, State};
 use std::{
     cell::RefCell,
+    cmp::Ordering,
     net::Ipv4Addr,
     rc::Rc,
     time::{Duration, Instant},
@@ -23,7 +34,7 @@ fn host_service(host: &mut Host<Rc<RefCell<Player>>>, state: &mut State) {
         return;
     };
 
-    let Some(mut event) = event else {
+    let Some(event) = event else {
         return;
     };
 
@@ -48,458 +59,1295 @@ fn host_service(host: &mut Host<Rc<RefCell<Player>>>, state: &mut State) {
                 return;
             };
 
-            let Ok(bson) = bson::from_slice::<Document>(packet.data()) else {
-                println!("Invalid bson");
-                event.peer_mut().disconnect_later(0);
-                return;
+            let request = match Request::decode(packet.data()) {
+                Ok(request) => request,
+                Err(error) => {
+                    let packet_id = error.packet_id();
+                    reject_packet(state, &player.borrow(), packet_id, error.into());
+                    return;
+                }
             };
 
-            handle_incoming_packet(state, player, event.peer_mut(), bson);
+            let packet_id = request.packet_id();
+            if let Err(error) = handle_incoming_packet(state, player.clone(), request) {
+                reject_packet(state, &player.borrow(), Some(packet_id), error);
+            }
         }
     }
 }
 
+/// Tells the client why its packet was refused and drops it if the packet
+/// was abusive.
+fn reject_packet(state: &mut State, player: &Player, packet_id: Option<u32>, error: PacketError) {
+    println!("Packet {:?} refused: {}", packet_id, error);
+
+    player.send_error(packet_id, &error);
+
+    if error.code.disconnects() {
+        state.disconnect_after_send(player.peer_id);
+    }
+}
+
 fn handle_incoming_packet(
     state: &mut State,
     player: Rc<RefCell<Player>>,
-    peer: &mut Peer<Rc<RefCell<Player>>>,
-    bson: Document,
-) {
+    request: Request,
+) -> Result<(), PacketError> {
     let rc_player = player.clone();
     let player = &mut player.borrow_mut();
 
-    let Ok(id) = bson.get_i32("PacketID") else {
-        println!("PacketID not found");
-        peer.disconnect_later(0);
-        return;
-    };
+    if !player.session.allows(&request) {
+        return Err(PacketError::new(
+            ErrorCode::InvalidState,
+            format!(
+                "Packet {} is not allowed while {:?}",
+                request.packet_id(),
+                player.session
+            ),
+        ));
+    }
 
-    match id as u32 {
-        protocol::PACKET_ID_AUTHENTICATION => {
-            let Ok(rid) = bson.get_str("RID") else {
-                println!("Auth RID not found");
-                peer.disconnect_later(0);
-                return;
-            };
+    match request {
+        Request::Hello(request) => {
+            if player.protocol_version.is_some() {
+                return Err(PacketError::new(ErrorCode::Abuse, "Hello sent twice"));
+            }
 
-            let Ok(exist) = state.database.is_player_exist_by_rid(rid) else {
-                println!("is_player_exist_by_rid fails");
-                player.send_auth_response("", &[], "", true);
-                peer.disconnect_later(0);
-                return;
-            };
+            player.protocol_version = Some(request.protocol_version);
 
-            if !exist {
-                if state.database.insert_new_player(rid).is_err() {
-                    println!("insert_new_player fails");
-                    player.send_auth_response("", &[], "", true);
-                    peer.disconnect_later(0);
-                    return;
-                };
-            }
+            if request.protocol_version < protocol::MIN_PROTOCOL_VERSION {
+                println!(
+                    "Rejecting client {:?} with protocol {}",
+                    request.client_version, request.protocol_version
+                );
 
-            if state.database.load_player_by_rid(rid, player).is_err() {
-                println!("load_player_by_rid fails");
-                player.send_auth_response("", &[], "", true);
-                peer.disconnect_later(0);
-                return;
+                player.send_hello(false, "Please update the game to keep playing.");
+                state.disconnect_after_send(player.peer_id);
+                return Ok(());
             }
 
-            player.send_auth_response(&player.name, &player.pfp_blob, &player.pfp_ext, false);
+            player.send_hello(true, "");
         }
 
-        protocol::PACKET_ID_ADD_QUEUE => {
-            let Ok(competitive) = bson.get_bool("Competitive") else {
-                println!("Competitive option not found");
-                peer.disconnect_later(0);
-                return;
+        Request::Authentication(request) => {
+            let version = *player
+                .protocol_version
+                .get_or_insert(protocol::LEGACY_PROTOCOL_VERSION);
+
+            if version < protocol::MIN_PROTOCOL_VERSION {
+                player.send_hello(false, "Please update the game to keep playing.");
+                state.disconnect_after_send(player.peer_id);
+                return Ok(());
+            }
+
+            let Some(credentials) = request.into_credentials() else {
+                return Err(PacketError::new(
+                    ErrorCode::MalformedPacket,
+                    "Authentication needs a token, a username and password or an RID",
+                ));
             };
 
-            if let Some(queue) = *state.last_queue.get(competitive as usize).unwrap() {
-                if queue == player.peer_id {
-                    println!("already in queue");
-                    peer.disconnect_later(0);
-                    *state.last_queue.get_mut(competitive as usize).unwrap() = None;
-                    return;
-                }
+            player.session = SessionState::Authenticating;
 
-                let Some(other) = state.get_player(queue) else {
-                    println!("other player left");
-                    peer.disconnect_later(0);
-                    *state.last_queue.get_mut(competitive as usize).unwrap() = None;
-                    return;
+            let requester = player.requester();
+            let ttl_secs = state.config.auth.token_ttl_secs;
+            let unsaved_ranks = state.database.unsaved_ranks();
+            state
+                .database
+                .execute(credentials.key().to_string(), move |db| {
+                    let unsaved_ranks = unsaved_ranks.snapshot();
+                    let result =
+                        auth::authenticate(db, credentials, ttl_secs).map(|mut session| {
+                            if let Some(rank) = unsaved_ranks.get(&session.record.id) {
+                                session.record.apply_rank(rank);
+                            }
+
+                            session
+                        });
+
+                    Some(DatabaseEvent::Authenticated { requester, result })
+                });
+        }
+
+        Request::RegisterCredentials(request) => {
+            if player.username.is_some() {
+                return Err(PacketError::new(
+                    ErrorCode::InvalidState,
+                    "Account already has a username",
+                ));
+            }
+
+            auth::validate_credentials(
+                &request.username,
+                &request.password,
+                state.config.auth.min_password_length,
+            )
+            .map_err(|e| PacketError::new(ErrorCode::InvalidArgument, e))?;
+
+            let (requester, id) = (player.requester(), player.id);
+            let (username, password) = (request.username, request.password);
+            state.database.execute(id, move |db| {
+                let result = auth::hash_password(&password)
+                    .and_then(|hash| db.try_save_credentials(id, &username, &hash));
+
+                Some(DatabaseEvent::CredentialsRegistered {
+                    requester,
+                    username,
+                    result,
+                })
+            });
+        }
+
+        Request::RefreshToken => {
+            let (requester, id) = (player.requester(), player.id);
+            let old_hash = player.token_hash.clone();
+            let ttl_secs = state.config.auth.token_ttl_secs;
+            state.database.execute(id, move |db| {
+                Some(DatabaseEvent::TokenRefreshed {
+                    requester,
+                    result: auth::refresh_token(db, id, old_hash.as_deref(), ttl_secs),
+                })
+            });
+        }
+
+        Request::RevokeTokens(request) => {
+            let (requester, id) = (player.requester(), player.id);
+            let token_hash = player.token_hash.clone();
+            state.database.execute(id, move |db| {
+                let result = match (request.all, token_hash) {
+                    (true, _) => db.remove_session_tokens(id),
+                    (false, Some(token_hash)) => db.remove_session_token(&token_hash).map(|_| 1),
+                    (false, None) => Ok(0),
                 };
 
-                let other_rc = other.clone();
-                let mut other = other.borrow_mut();
-                other.send_match_notify(&player.name, &player.pfp_blob, &player.pfp_ext);
-                player.send_match_notify(&other.name, &other.pfp_blob, &other.pfp_ext);
+                Some(DatabaseEvent::TokensRevoked { requester, result })
+            });
+        }
 
-                let game_index = state.add_game(rc_player, other_rc, competitive);
-                other.game_index = game_index;
-                player.game_index = game_index;
+        Request::AddQueue(request) => {
+            check_player_count(request.player_count)?;
+
+            let mode = QueueMode {
+                competitive: request.competitive,
+                length: request.length,
+                players: request.player_count,
+                filter: QuestionFilter::new(
+                    request.category,
+                    request.language,
+                    request.difficulty_curve,
+                ),
+            };
+            check_filter(&state.questions, &mode.filter)?;
 
-                *state.last_queue.get_mut(competitive as usize).unwrap() = None;
-            } else {
-                *state.last_queue.get_mut(competitive as usize).unwrap() = Some(player.peer_id);
+            state.matchmaker.join(mode, player.peer_id, player.rating);
+            player.session = SessionState::InQueue;
+            player.send_queue_status(state.matchmaker.position(player.peer_id));
+        }
+
+        // Backing out twice, or before queueing, just confirms it
+        Request::CancelQueue => {
+            state.matchmaker.leave(player.peer_id);
+            player.session = SessionState::Authenticated;
+            player.send_queue_status(None);
+        }
+
+        Request::FetchQueueStatus => {
+            player.send_queue_status(state.matchmaker.position(player.peer_id));
+        }
+
+        Request::CreateLobby(request) => {
+            check_player_count(request.player_count)?;
+
+            let filter =
+                QuestionFilter::new(request.category, request.language, request.difficulty_curve);
+            check_filter(&state.questions, &filter)?;
+
+            let code = state.lobbies.create(
+                player.peer_id,
+                player.id,
+                request.length,
+                None,
+                request.player_count,
+                filter,
+            );
+
+            player.session = SessionState::InLobby;
+            player.send_lobby_created(&code, request.length, request.player_count);
+        }
+
+        Request::JoinLobby(request) => join_lobby(state, &rc_player, player, &request.code, false)?,
+
+        Request::LeaveLobby => {
+            if let Some(lobby) = state.leave_lobby(player) {
+                player.send_lobby_closed(&lobby.code, "Left the lobby");
+            }
+
+            player.session = SessionState::Authenticated;
+        }
+
+        Request::ChallengeFriend(request) => {
+            if request.id == player.id {
+                return Err(PacketError::new(ErrorCode::Abuse, "Challenge sent to self"));
             }
+
+            let (requester, id) = (player.requester(), player.id);
+            let (target, length) = (request.id, request.length);
+            state.database.execute(id, move |db| {
+                Some(DatabaseEvent::FriendChallenged {
+                    requester,
+                    target,
+                    length,
+                    result: db.is_friend(id, target),
+                })
+            });
         }
 
-        protocol::PACKET_ID_QUESTION_ANSWER => {
+        Request::AcceptChallenge(request) => {
+            join_lobby(state, &rc_player, player, &request.code, true)?
+        }
+
+        Request::DeclineChallenge(request) => {
+            let invited = state
+                .lobbies
+                .get(&request.code)
+                .is_some_and(|x| x.invited == Some(player.id));
+
+            if !invited {
+                return Err(PacketError::new(
+                    ErrorCode::InvalidArgument,
+                    "Challenge not found",
+                ));
+            }
+
+            let lobby = state.lobbies.remove(&request.code).unwrap();
+            if let Some(host) = state.get_player(lobby.host) {
+                let mut host = host.borrow_mut();
+                host.session = SessionState::Authenticated;
+                host.send_lobby_closed(&lobby.code, "Challenge declined");
+            }
+        }
+
+        Request::QuestionAnswer(request) => {
             if player.answered {
-                println!("Player already answered");
-                peer.disconnect_later(0);
-                return;
+                return Err(PacketError::new(
+                    ErrorCode::InvalidState,
+                    "Player already answered",
+                ));
             }
 
-            let Ok(answer_index) = bson.get_i32("AnswerIndex") else {
-                println!("AnswerIndex option not found");
-                peer.disconnect_later(0);
-                return;
+            let Some(game) = player.game_id.and_then(|id| state.games.get_mut(&id)) else {
+                return Err(PacketError::new(ErrorCode::InvalidState, "Game not found"));
             };
 
-            if player.game_index == usize::MAX {
-                println!("Player not in game");
-                peer.disconnect_later(0);
-                return;
+            if game.phase != Phase::QuestionOpen {
+                return Err(PacketError::new(
+                    ErrorCode::InvalidState,
+                    "Question is not open",
+                ));
             }
 
-            let game = state.games.get_mut(player.game_index).unwrap();
-            if game.question.answer_index == answer_index {
-                player.points += 15 - game.stage_timer.elapsed().as_secs() as i32;
+            if game.question.answer_index == request.answer_index {
+                player.points += game.rules.points(game.phase_started.elapsed().as_secs());
             }
 
             player.answered = true;
             player.send_answer(game.question.answer_index);
 
-            let other = if game.p1.try_borrow().is_err() {
-                game.p2.clone()
-            } else {
-                game.p1.clone()
-            };
+            // Players who dropped out cannot answer, so they are not waited for
+            let all_answered = game.opponents(&rc_player).all(|x| {
+                let x = x.borrow();
+                x.answered || x.forfeited || x.disconnected_at.is_some()
+            });
 
-            let other = other.borrow();
-            if other.answered && game.stage_timer.elapsed().as_secs() < 12 {
-                game.stage_timer = Instant::now() - Duration::from_secs(12);
+            if all_answered {
+                game.close_question();
             }
         }
 
-        protocol::PACKET_ID_FETCH_LEADERBOARD => {
-            let Ok(leaderboard) = state.database.get_leaderboard() else {
-                println!("get leaderboard fails");
-                peer.disconnect_later(0);
-                return;
-            };
+        Request::FetchLeaderboard => {
+            let requester = player.requester();
+            state.database.execute_unordered(move |db| {
+                Some(DatabaseEvent::Leaderboard {
+                    requester,
+                    result: db.get_leaderboard(),
+                })
+            });
+        }
 
-            player.send_leaderboard(leaderboard);
+        Request::ChangeProfilePicture(request) => {
+            if request.pfp_blob.len() > 10 * 1024 * 1024 {
+                return Err(PacketError::new(ErrorCode::Abuse, "File too big"));
+            }
+
+            let (requester, id) = (player.requester(), player.id);
+            let (pfp_blob, extension) = (request.pfp_blob, request.extension);
+            state.database.execute(id, move |db| {
+                let result = db.update_profile_picture(id, &pfp_blob, &extension);
+
+                Some(DatabaseEvent::ProfilePictureChanged {
+                    requester,
+                    pfp_blob,
+                    extension,
+                    result,
+                })
+            });
         }
 
-        protocol::PACKET_ID_CHANGE_PROFILE_PICTURE => {
-            let Ok(extension) = bson.get_str("ProfilePictureExtension") else {
-                println!("ProfilePictureExtension option not found");
-                peer.disconnect_later(0);
-                return;
-            };
+        Request::UpdateName(request) => {
+            let (requester, id) = (player.requester(), player.id);
+            let name = request.name;
+            state.database.execute(id, move |db| {
+                let result = db.try_rename_player(id, &name);
+
+                Some(DatabaseEvent::NameUpdated {
+                    requester,
+                    name,
+                    result,
+                })
+            });
+        }
 
-            let Ok(pfp_blob) = bson.get_binary_generic("ProfilePicture") else {
-                println!("ProfilePicture option not found");
-                peer.disconnect_later(0);
-                return;
-            };
+        Request::FetchFriends => {
+            let (requester, id) = (player.requester(), player.id);
+            state.database.execute(id, move |db| {
+                Some(DatabaseEvent::Friends {
+                    requester,
+                    result: db.get_friends_with_pfp(id),
+                })
+            });
+        }
 
-            if pfp_blob.len() > 10 * 1024 * 1024 {
-                println!("File too big");
-                peer.disconnect_later(0);
-                return;
+        Request::FetchFriendRequests => {
+            let (requester, id) = (player.requester(), player.id);
+            state.database.execute(id, move |db| {
+                Some(DatabaseEvent::FriendRequests {
+                    requester,
+                    result: db.get_friend_requests_with_pfp(id),
+                })
+            });
+        }
+
+        Request::AcceptFriendRequest(request) => {
+            let id = request.id;
+            let (requester, player_id) = (player.requester(), player.id);
+            state.database.execute(player_id, move |db| {
+                let error = db.accept_friend_request(player_id, id).err()?;
+
+                Some(DatabaseEvent::Failed {
+                    requester,
+                    packet_id: protocol::PACKET_ID_ACCEPT_FRIEND_REQUEST,
+                    context: "Accept friend request error",
+                    error,
+                })
+            });
+        }
+
+        Request::DeclineFriendRequest(request) => {
+            let id = request.id;
+            let (requester, player_id) = (player.requester(), player.id);
+            state.database.execute(player_id, move |db| {
+                let error = db.remove_friend_request(player_id, id).err()?;
+
+                Some(DatabaseEvent::Failed {
+                    requester,
+                    packet_id: protocol::PACKET_ID_DECLINE_FRIEND_REQUEST,
+                    context: "Remove friend request error",
+                    error,
+                })
+            });
+        }
+
+        Request::SearchName(request) => {
+            if player.name.eq_ignore_ascii_case(&request.name) {
+                player.send_search_name(false, "", &[], "");
+                return Ok(());
             }
 
-            state
-                .database
-                .update_profile_picture(player.id, &pfp_blob, extension)
-                .unwrap();
+            let requester = player.requester();
+            let name = request.name;
+            state.database.execute_unordered(move |db| {
+                let result = match db.is_player_exist_by_name(&name) {
+                    Ok(true) => db.get_pfp_by_name(&name).map(Some),
+                    Ok(false) => Ok(None),
+                    Err(error) => Err(error),
+                };
+
+                Some(DatabaseEvent::SearchName {
+                    requester,
+                    name,
+                    result,
+                })
+            });
+        }
+
+        Request::AddFriendRequest(request) => {
+            let id = request.id;
+            if id == player.id {
+                return Err(PacketError::new(
+                    ErrorCode::Abuse,
+                    "Friend request sent to self",
+                ));
+            }
+
+            let (requester, player_id) = (player.requester(), player.id);
+            state.database.execute(player_id, move |db| {
+                let error = db.add_friend_request(id, player_id).err()?;
+
+                Some(DatabaseEvent::Failed {
+                    requester,
+                    packet_id: protocol::PACKET_ID_ADD_FRIEND_REQUEST,
+                    context: "Add friend request error",
+                    error,
+                })
+            });
+        }
+    }
+
+    Ok(())
+}
 
-            player.pfp_blob = pfp_blob.to_vec();
-            player.pfp_ext = extension.to_string();
-            player.send_change_profile_picture(&pfp_blob, extension);
+fn poll_database(state: &mut State) {
+    while let Some(event) = state.database.poll() {
+        match event {
+            DatabaseEvent::QuestionsLoaded { result } => reload_questions(state, result),
+            event => handle_database_event(state, event),
         }
+    }
+}
+
+/// Queues a reload of the question bank every `question_reload_secs`.
+fn poll_questions(state: &mut State) {
+    let interval_secs = state.config.game.question_reload_secs;
+    if interval_secs == 0 || state.questions_loaded_at.elapsed().as_secs() < interval_secs {
+        return;
+    }
 
-        protocol::PACKET_ID_UPDATE_NAME => {
-            let Ok(name) = bson.get_str("Name") else {
-                println!("Name option not found");
-                peer.disconnect_later(0);
+    state.questions_loaded_at = Instant::now();
+    state.database.execute_unordered(|db| {
+        Some(DatabaseEvent::QuestionsLoaded {
+            result: db.get_all_questions(),
+        })
+    });
+}
+
+/// Swaps in the reloaded question bank. A failed or empty reload keeps the
+/// current one, so a broken table never stops new matches.
+fn reload_questions(state: &mut State, result: anyhow::Result<Vec<GameQuestion>>) {
+    match result {
+        Ok(questions) if questions.is_empty() => {
+            println!("Reloaded question bank is empty, keeping the current one")
+        }
+        Ok(questions) => {
+            if questions.len() != state.questions.len() {
+                println!(
+                    "Question bank reloaded, {} questions (was {})",
+                    questions.len(),
+                    state.questions.len()
+                );
+            }
+
+            state.questions = questions;
+        }
+        Err(error) => println!("Reload questions error: {:#}", error),
+    }
+}
+
+/// Logs a failed query and reports it to the client, the connection stays up
+/// so the client may retry.
+fn report_database_error(player: &Player, packet_id: u32, context: &str, error: anyhow::Error) {
+    println!("{}: {:#}", context, error);
+
+    player.send_error(
+        Some(packet_id),
+        &PacketError::new(ErrorCode::DatabaseError, context),
+    );
+}
+
+/// Moves another connection logged in to the same account onto `player` and
+/// drops it, so one account never has two players. A queue spot or running
+/// game is handed over as well, which is also how a player who dropped out
+/// of a match resumes it.
+fn take_over_session(state: &mut State, rc_player: &Rc<RefCell<Player>>, player: &mut Player) {
+    let in_game = state.games.values().flat_map(|game| &game.players);
+    let old_rc = state
+        .players
+        .values()
+        .chain(in_game)
+        .find(|x| {
+            !Rc::ptr_eq(x, rc_player)
+                && x.try_borrow().is_ok_and(|x| {
+                    x.id == player.id && x.session.is_authenticated() && !x.forfeited
+                })
+        })
+        .cloned();
+
+    let Some(old_rc) = old_rc else {
+        return;
+    };
+
+    let mut old = old_rc.borrow_mut();
+    let reconnect = old.disconnected_at.is_some();
+    if reconnect {
+        println!("Player {} reconnected", player.id);
+    } else {
+        println!(
+            "Player {} logged in again, replacing the old session",
+            player.id
+        );
+    }
+
+    // The in-memory rank may be ahead of a save that is still queued
+    player.rating = old.rating;
+    player.rating_deviation = old.rating_deviation;
+    player.rating_volatility = old.rating_volatility;
+    player.win_count = old.win_count;
+    player.lose_count = old.lose_count;
+    player.draw_count = old.draw_count;
+
+    match old.session {
+        SessionState::InQueue => {
+            state.matchmaker.replace(old.peer_id, player.peer_id);
+            player.session = SessionState::InQueue;
+            player.send_queue_status(state.matchmaker.position(player.peer_id));
+        }
+
+        SessionState::InGame => {
+            if let Some(game) = old.game_id.and_then(|id| state.games.get_mut(&id)) {
+                game.replace(&old_rc, rc_player.clone());
+
+                player.session = SessionState::InGame;
+                player.game_id = Some(game.id);
+                player.points = old.points;
+                player.answered = old.answered;
+
+                let opponents = game
+                    .opponents(rc_player)
+                    .map(|x| x.borrow())
+                    .collect::<Vec<_>>();
+
+                if reconnect {
+                    for opponent in opponents.iter() {
+                        opponent.send_opponent_status(&player.name, true, 0);
+                    }
+                }
+
+                let mut everyone = opponents.iter().map(|x| &**x).collect::<Vec<_>>();
+                player.send_match_notify(&everyone, &game.rules);
+                if game.round > 0 {
+                    everyone.push(player);
+                    let standings = game::standings(&everyone);
+                    player.send_question_update(&standings, &game.question);
+                }
+
+                player.send_phase_changed(game);
+            }
+        }
+
+        SessionState::InLobby => {
+            let lobby = state.lobbies.replace_member(old.peer_id, player.peer_id);
+            if let Some(lobby) = lobby.cloned() {
+                player.send_lobby_created(&lobby.code, lobby.length, lobby.capacity);
+                state.send_lobby_updated(&lobby, player);
+            }
+
+            player.session = SessionState::InLobby;
+        }
+
+        SessionState::Connected | SessionState::Authenticating | SessionState::Authenticated => {}
+    }
+
+    // Nothing of the old connection must be touched once it disconnects
+    old.session = SessionState::Connected;
+    old.game_id = None;
+
+    if !reconnect {
+        old.send_kicked("Logged in from another device");
+        state.disconnect_after_send(old.peer_id);
+    }
+}
+
+fn handle_database_event(state: &mut State, event: DatabaseEvent) {
+    let Some(requester) = event.requester() else {
+        return;
+    };
+
+    let Some(rc_player) = state.get_player(requester.peer_id) else {
+        return;
+    };
+
+    let player = &mut rc_player.borrow_mut();
+
+    // The connection that asked is gone and its peer ID was handed out again
+    if player.serial != requester.serial {
+        return;
+    }
+
+    match event {
+        DatabaseEvent::Authenticated { result, .. } => {
+            if player.session != SessionState::Authenticating {
                 return;
+            }
+
+            let session = match result {
+                Ok(session) => session,
+                Err(error) => {
+                    player.session = SessionState::Connected;
+                    player.send_auth_failed();
+
+                    if let Some(invalid) = error.downcast_ref::<InvalidCredentials>() {
+                        player.send_error(
+                            Some(protocol::PACKET_ID_AUTHENTICATION),
+                            &PacketError::new(ErrorCode::Unauthorized, invalid.0),
+                        );
+                    } else {
+                        report_database_error(
+                            player,
+                            protocol::PACKET_ID_AUTHENTICATION,
+                            "Authentication error",
+                            error,
+                        );
+                    }
+
+                    return;
+                }
             };
 
-            let Ok(result) = state.database.is_player_exist_by_name(name) else {
-                println!("is_player_exist_by_name fails");
-                peer.disconnect_later(0);
-                return;
+            player.load_record(session.record);
+            player.token_hash = Some(session.token.hash.clone());
+            player.session = SessionState::Authenticated;
+
+            player.send_auth_response(&session.token);
+            take_over_session(state, &rc_player, player);
+        }
+
+        DatabaseEvent::CredentialsRegistered {
+            username, result, ..
+        } => {
+            let taken = match result {
+                Ok(taken) => taken,
+                Err(error) => {
+                    report_database_error(
+                        player,
+                        protocol::PACKET_ID_REGISTER_CREDENTIALS,
+                        "Register credentials error",
+                        error,
+                    );
+                    return;
+                }
             };
 
-            if !result {
-                state.database.save_player_name(player.id, name).unwrap();
-                player.name = name.to_string();
-            }
+            player.send_credentials_registered(&username, taken);
 
-            player.send_update_name(name, result);
+            if !taken {
+                player.username = Some(username);
+            }
         }
 
-        protocol::PACKET_ID_FETCH_FRIENDS => {
-            let Ok(mut friends) = state.database.get_friends(player.id) else {
-                println!("get friends fails");
-                peer.disconnect_later(0);
+        DatabaseEvent::TokenRefreshed { result, .. } => match result {
+            Ok(token) => {
+                player.send_token_refreshed(&token);
+                player.token_hash = Some(token.hash);
+            }
+            Err(error) => report_database_error(
+                player,
+                protocol::PACKET_ID_REFRESH_TOKEN,
+                "Refresh token error",
+                error,
+            ),
+        },
+
+        // Either way the token of this session is gone
+        DatabaseEvent::TokensRevoked { result, .. } => match result {
+            Ok(count) => {
+                player.token_hash = None;
+                player.send_tokens_revoked(count);
+            }
+            Err(error) => report_database_error(
+                player,
+                protocol::PACKET_ID_REVOKE_TOKENS,
+                "Revoke tokens error",
+                error,
+            ),
+        },
+
+        DatabaseEvent::Leaderboard { result, .. } => match result {
+            Ok(leaderboard) => player.send_leaderboard(leaderboard),
+            Err(error) => report_database_error(
+                player,
+                protocol::PACKET_ID_FETCH_LEADERBOARD,
+                "Get leaderboard error",
+                error,
+            ),
+        },
+
+        DatabaseEvent::ProfilePictureChanged {
+            pfp_blob,
+            extension,
+            result,
+            ..
+        } => {
+            if let Err(error) = result {
+                report_database_error(
+                    player,
+                    protocol::PACKET_ID_CHANGE_PROFILE_PICTURE,
+                    "Update profile picture error",
+                    error,
+                );
                 return;
+            }
+
+            player.send_change_profile_picture(&pfp_blob, &extension);
+            player.pfp_blob = pfp_blob;
+            player.pfp_ext = extension;
+        }
+
+        DatabaseEvent::NameUpdated { name, result, .. } => {
+            let taken = match result {
+                Ok(taken) => taken,
+                Err(error) => {
+                    report_database_error(
+                        player,
+                        protocol::PACKET_ID_UPDATE_NAME,
+                        "Update name error",
+                        error,
+                    );
+                    return;
+                }
+            };
+
+            player.send_update_name(&name, taken);
+
+            if !taken {
+                player.name = name;
+            }
+        }
+
+        DatabaseEvent::Friends { result, .. } => {
+            let mut friends = match result {
+                Ok(friends) => friends,
+                Err(error) => {
+                    report_database_error(
+                        player,
+                        protocol::PACKET_ID_FETCH_FRIENDS,
+                        "Get friends error",
+                        error,
+                    );
+                    return;
+                }
             };
 
             for friend in friends.iter_mut() {
-                let mut found = false;
                 for ply in state.players.values() {
                     if let Ok(ply) = ply.try_borrow() {
                         if ply.id == friend.id {
                             friend.online = true;
                             friend.pfp = ply.pfp_blob.clone();
                             friend.pfp_ext = ply.pfp_ext.clone();
-                            found = true;
                         }
                     }
                 }
-
-                if !found {
-                    let (pfp, pfp_ext) = state.database.get_pfp(player.id).unwrap();
-                    friend.pfp = pfp;
-                    friend.pfp_ext = pfp_ext;
-                }
             }
 
             player.send_friends(friends);
         }
 
-        protocol::PACKET_ID_FETCH_FRIEND_REQUESTS => {
-            let Ok(mut friend_requests) = state.database.get_friend_requests(player.id) else {
-                println!("get friend requests fails");
-                peer.disconnect_later(0);
-                return;
+        DatabaseEvent::FriendRequests { result, .. } => {
+            let mut friend_requests = match result {
+                Ok(friend_requests) => friend_requests,
+                Err(error) => {
+                    report_database_error(
+                        player,
+                        protocol::PACKET_ID_FETCH_FRIEND_REQUESTS,
+                        "Get friend requests error",
+                        error,
+                    );
+                    return;
+                }
             };
 
             for friend in friend_requests.iter_mut() {
-                let mut found = false;
                 for ply in state.players.values() {
                     if let Ok(ply) = ply.try_borrow() {
                         if ply.id == friend.id {
                             friend.pfp = ply.pfp_blob.clone();
                             friend.pfp_ext = ply.pfp_ext.clone();
-                            found = true;
                         }
                     }
                 }
-
-                if !found {
-                    let (pfp, pfp_ext) = state.database.get_pfp(player.id).unwrap();
-                    friend.pfp = pfp;
-                    friend.pfp_ext = pfp_ext;
-                }
             }
 
             player.send_friend_requests(friend_requests);
         }
 
-        protocol::PACKET_ID_ACCEPT_FRIEND_REQUEST => {
-            let Ok(id) = bson.get_i32("ID") else {
-                println!("ID option not found");
-                peer.disconnect_later(0);
-                return;
+        DatabaseEvent::FriendChallenged {
+            target,
+            length,
+            result,
+            ..
+        } => {
+            let is_friend = match result {
+                Ok(is_friend) => is_friend,
+                Err(error) => {
+                    report_database_error(
+                        player,
+                        protocol::PACKET_ID_CHALLENGE_FRIEND,
+                        "Challenge friend error",
+                        error,
+                    );
+                    return;
+                }
             };
 
-            if let Err(error) = state.database.remove_friend_request(id, player.id) {
-                println!("Remove friend request error: {}", error);
-                peer.disconnect_later(0);
-                return;
-            }
-
-            if let Err(error) = state.database.add_friend(player.id, id) {
-                println!("Add friend #1 error: {}", error);
-                peer.disconnect_later(0);
-                return;
-            }
-
-            if let Err(error) = state.database.add_friend(id, player.id) {
-                println!("Add friend #2 error: {}", error);
-                peer.disconnect_later(0);
-                return;
-            }
-        }
-
-        protocol::PACKET_ID_DECLINE_FRIEND_REQUEST => {
-            let Ok(id) = bson.get_i32("ID") else {
-                println!("ID option not found");
-                peer.disconnect_later(0);
-                return;
+            let error = if !is_friend {
+                Some(PacketError::new(ErrorCode::InvalidArgument, "Not a friend"))
+            } else if player.session != SessionState::Authenticated {
+                // Queued or joined something else while the query ran
+                Some(PacketError::new(ErrorCode::InvalidState, "Player is busy"))
+            } else {
+                None
             };
 
-            if let Err(error) = state.database.remove_friend_request(id, player.id) {
-                println!("Remove friend request error: {}", error);
-                peer.disconnect_later(0);
+            if let Some(error) = error {
+                player.send_error(Some(protocol::PACKET_ID_CHALLENGE_FRIEND), &error);
                 return;
             }
-        }
 
-        protocol::PACKET_ID_SEARCH_NAME => {
-            let Ok(name) = bson.get_str("Name") else {
-                println!("Name option not found");
-                peer.disconnect_later(0);
+            let Some(friend) = state.get_player_by_id(target) else {
+                let error = PacketError::new(ErrorCode::InvalidState, "Friend is offline");
+                player.send_error(Some(protocol::PACKET_ID_CHALLENGE_FRIEND), &error);
                 return;
             };
 
-            let Ok(exist) = state.database.is_player_exist_by_name(name) else {
-                player.send_search_name(false, "", &[], "");
-                return;
-            };
-
-            if !exist || player.name.eq_ignore_ascii_case(name) {
-                player.send_search_name(false, "", &[], "");
-            } else {
-                let Ok((pfp_blob, pfp_ext)) = state.database.get_pfp_by_name(name) else {
-                    player.send_search_name(false, "", &[], "");
-                    return;
-                };
-
-                player.send_search_name(true, name, &pfp_blob, &pfp_ext)
-            }
+            let code = state.lobbies.create(
+                player.peer_id,
+                player.id,
+                length,
+                Some(target),
+                2,
+                QuestionFilter::default(),
+            );
+
+            player.session = SessionState::InLobby;
+            player.send_lobby_created(&code, length, 2);
+            friend.borrow().send_challenge(player, &code, length);
         }
 
-        protocol::PACKET_ID_ADD_FRIEND_REQUEST => {
-            let Ok(id) = bson.get_i32("ID") else {
-                println!("ID option not found");
-                peer.disconnect_later(0);
-                return;
-            };
-
-            if id == player.id {
-                println!("Illegal");
-                peer.disconnect_later(0);
-                return;
+        DatabaseEvent::SearchName { name, result, .. } => match result {
+            Ok(Some((pfp_blob, pfp_ext))) => {
+                player.send_search_name(true, &name, &pfp_blob, &pfp_ext)
             }
+            Ok(None) => player.send_search_name(false, "", &[], ""),
+            Err(error) => {
+                player.send_search_name(false, "", &[], "");
+                report_database_error(
+                    player,
+                    protocol::PACKET_ID_SEARCH_NAME,
+                    "Search name error",
+                    error,
+                );
+            }
+        },
 
-            let _ = state.database.add_friend_request(id, player.id);
-        }
+        DatabaseEvent::Failed {
+            packet_id,
+            context,
+            error,
+            ..
+        } => report_database_error(player, packet_id, context, error),
 
-        _ => peer.disconnect_later(0),
+        // Has no player, see `poll_database`
+        DatabaseEvent::QuestionsLoaded { .. } => {}
     }
 }
 
-fn send_packets(host: &mut Host<Rc<RefCell<Player>>>, state: &State) {
+fn send_packets(host: &mut Host<Rc<RefCell<Player>>>, state: &mut State) {
     let mut packets = state.packets.borrow_mut();
     for _ in 0..packets.len() {
         let (peer_id, data) = packets.remove(0);
-        let Some(peer)= host.peer_mut(peer_id) else {
-            return;
+        let Some(peer) = host.peer_mut(peer_id) else {
+            continue;
         };
 
         let packet = Packet::new(data, PacketMode::ReliableSequenced).unwrap();
         peer.send_packet(packet, 0).unwrap();
     }
+
+    // ENet waits for the outgoing queue to drain before disconnecting
+    for peer_id in state.pending_disconnects.drain(..) {
+        if let Some(peer) = host.peer_mut(peer_id) {
+            peer.disconnect_later(0);
+        }
+    }
 }
 
-fn poll_game(state: &mut State) {
-    let mut to_remove = Vec::new();
+/// Matches hold from two players up to `MAX_PLAYERS`.
+fn check_player_count(count: usize) -> Result<(), PacketError> {
+    if !(2..=MAX_PLAYERS).contains(&count) {
+        return Err(PacketError::new(
+            ErrorCode::InvalidArgument,
+            format!("Matches need 2 to {} players", MAX_PLAYERS),
+        ));
+    }
 
-    for (i, game) in state.games.iter_mut().enumerate().rev() {
-        let send_question_update = (!game.started && game.start_timer.elapsed().as_secs() >= 3)
-            || (game.started && game.stage_timer.elapsed().as_secs() >= 15);
+    Ok(())
+}
 
-        if send_question_update {
-            let mut rng = rand::thread_rng();
-            let mut index;
-            loop {
-                index = rng.gen_range(0..state.questions.len());
-                if game.previous_questions.contains(&index) {
-                    continue;
-                }
+/// At least one question of the bank has to match the filter, every other
+/// filter would only open another queue.
+fn check_filter(questions: &[GameQuestion], filter: &QuestionFilter) -> Result<(), PacketError> {
+    if !questions.iter().any(|x| filter.matches(x)) {
+        return Err(PacketError::new(
+            ErrorCode::InvalidArgument,
+            "No question has this category and language",
+        ));
+    }
 
-                game.previous_questions.insert(index);
-                break;
-            }
+    Ok(())
+}
 
-            let question = state.questions.get(index).unwrap();
+/// Creates the game and tells every player about its opponents.
+fn start_match(
+    state: &mut State,
+    players: &mut [(&Rc<RefCell<Player>>, &mut Player)],
+    competitive: bool,
+    length: MatchLength,
+    filter: QuestionFilter,
+) {
+    let rules = state.config.rules.get(length).clone();
+    for (i, (_, player)) in players.iter().enumerate() {
+        let opponents = players
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, (_, x))| &**x)
+            .collect::<Vec<_>>();
+
+        player.send_match_notify(&opponents, &rules);
+    }
 
-            let mut p1 = game.p1.borrow_mut();
-            let mut p2 = game.p2.borrow_mut();
+    let rcs = players.iter().map(|(x, _)| (*x).clone()).collect();
+    let game_id = state.add_game(rcs, competitive, rules, filter);
+    let game = &state.games[&game_id];
+    for (_, player) in players.iter_mut() {
+        player.game_id = Some(game_id);
+        player.session = SessionState::InGame;
+        player.send_phase_changed(game);
+    }
+}
 
-            game.stage += 1;
-            game.question = question.clone();
-            game.stage_timer = Instant::now();
+/// Adds `player` to a private lobby, the match starts once it is full.
+/// Challenges may only be joined by the invited friend, with `challenge`
+/// set.
+fn join_lobby(
+    state: &mut State,
+    rc_player: &Rc<RefCell<Player>>,
+    player: &mut Player,
+    code: &str,
+    challenge: bool,
+) -> Result<(), PacketError> {
+    let joinable = state
+        .lobbies
+        .get(code)
+        .is_some_and(|x| x.invited.map_or(!challenge, |id| id == player.id));
+
+    if !joinable {
+        return Err(PacketError::new(
+            ErrorCode::InvalidArgument,
+            "Lobby not found",
+        ));
+    }
 
-            if game.stage > 2 {
-                let winner = if p1.points == p2.points {
-                    String::from("-")
-                } else if p1.points > p2.points {
-                    p1.name.clone()
-                } else {
-                    p2.name.clone()
-                };
+    let lobby = state.lobbies.get_mut(code).unwrap();
+    if lobby.is_member(player.id) {
+        return Err(PacketError::new(
+            ErrorCode::InvalidArgument,
+            "Cannot join your own lobby",
+        ));
+    }
 
-                p1.send_game_ended(&winner);
-                p2.send_game_ended(&winner);
-                p1.send_question_update(p1.points, p2.points, question);
-                p2.send_question_update(p2.points, p1.points, question);
-
-                if p1.points > p2.points {
-                    p1.win_count += 1;
-                    p2.lose_count += 1;
-                } else {
-                    p2.win_count += 1;
-                    p1.lose_count += 1;
-                }
+    println!("Player {} joined the lobby {}", player.id, lobby.code);
 
-                if game.competitive && p1.points != p2.points {
-                    if p1.points > p2.points {
-                        p1.rating += 10;
-                    } else {
-                        p2.rating += 10;
-                    }
-                }
+    if lobby.free_seats() > 1 {
+        lobby.guests.push((player.peer_id, player.id));
+        let lobby = lobby.clone();
 
-                state.database.save_rank(&p1);
-                state.database.save_rank(&p2);
+        player.session = SessionState::InLobby;
+        state.send_lobby_updated(&lobby, player);
+        return Ok(());
+    }
 
-                p1.points = 0;
-                p2.points = 0;
-                p1.answered = false;
-                p2.answered = false;
-                p1.game_index = usize::MAX;
-                p2.game_index = usize::MAX;
+    // Members are taken out of the lobby when they disconnect
+    let lobby = state.lobbies.remove(code).unwrap();
+    let rcs = lobby
+        .members()
+        .filter_map(|x| state.get_player(x))
+        .collect::<Vec<_>>();
 
-                to_remove.push(i);
-            } else {
-                if !game.started {
-                    game.started = true;
-                } else {
-                    p1.answered = false;
-                    p2.answered = false;
+    let mut members = rcs.iter().map(|x| x.borrow_mut()).collect::<Vec<_>>();
+    let mut players = rcs
+        .iter()
+        .zip(members.iter_mut().map(|x| &mut **x))
+        .collect::<Vec<_>>();
+
+    players.push((rc_player, player));
+    start_match(state, &mut players, false, lobby.length, lobby.filter);
+
+    Ok(())
+}
+
+fn poll_matchmaking(state: &mut State) {
+    for (mode, entries) in state.matchmaker.find_matches(&state.config.matchmaking) {
+        // Players leave the queue when they disconnect, so all are still here
+        let Some(rcs) = entries
+            .iter()
+            .map(|x| state.get_player(x.peer_id))
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+
+        let mut matched = rcs.iter().map(|x| x.borrow_mut()).collect::<Vec<_>>();
+        println!(
+            "Matched players {}",
+            matched
+                .iter()
+                .map(|x| format!("{} ({})", x.id, x.rating))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let mut players = rcs
+            .iter()
+            .zip(matched.iter_mut().map(|x| &mut **x))
+            .collect::<Vec<_>>();
+
+        start_match(
+            state,
+            &mut players,
+            mode.competitive,
+            mode.length,
+            mode.filter,
+        );
+    }
+
+    let interval_secs = state.config.matchmaking.status_interval_secs;
+    for peer_id in state.matchmaker.status_update_due(interval_secs) {
+        if let Some(player) = state.get_player(peer_id) {
+            player
+                .borrow()
+                .send_queue_status(state.matchmaker.position(peer_id));
+        }
+    }
+}
+
+/// Counts the result of every player, rates competitive matches as a game
+/// against each opponent, tells everyone the final standings and saves
+/// their rank. Only a sole first place wins, a shared one is a draw.
+fn record_results(database: &DatabasePool, game: &Game, reason: GameEndReason) {
+    let mut players = game
+        .players
+        .iter()
+        .map(|x| x.borrow_mut())
+        .collect::<Vec<_>>();
+
+    let standings = game::standings(&players.iter().map(|x| &**x).collect::<Vec<_>>());
+    let first = standings.iter().filter(|x| x.placement == 1).count();
+    let winner = match standings.iter().find(|x| x.placement == 1) {
+        Some(x) if first == 1 => x.name.clone(),
+        _ => String::new(),
+    };
+
+    // Everyone is rated from the ratings before the match
+    let glickos = players.iter().map(|x| x.glicko()).collect::<Vec<_>>();
+
+    for (i, player) in players.iter_mut().enumerate() {
+        let placement = standings[i].placement;
+        let outcome = if placement > 1 {
+            GameOutcome::Loss
+        } else if first > 1 {
+            GameOutcome::Draw
+        } else {
+            GameOutcome::Win
+        };
+
+        match outcome {
+            GameOutcome::Win => player.win_count += 1,
+            GameOutcome::Loss => player.lose_count += 1,
+            GameOutcome::Draw => player.draw_count += 1,
+        }
+
+        let rating_delta = if game.competitive {
+            let results = standings
+                .iter()
+                .zip(glickos.iter())
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, (x, glicko))| {
+                    let outcome = match x.placement.cmp(&placement) {
+                        Ordering::Greater => GameOutcome::Win,
+                        Ordering::Equal => GameOutcome::Draw,
+                        Ordering::Less => GameOutcome::Loss,
+                    };
+
+                    (*glicko, outcome.score())
+                })
+                .collect::<Vec<_>>();
+
+            player.rate(&results)
+        } else {
+            0
+        };
+
+        player.send_game_ended(outcome, &winner, reason, rating_delta, &standings);
+
+        database.save_rank(player.rank());
+    }
+}
+
+/// Moves `game` to `phase` and tells every player.
+fn enter_phase(game: &mut Game, phase: Phase) {
+    game.enter(phase);
+    for player in game.players.iter() {
+        player.borrow().send_phase_changed(game);
+    }
+}
+
+/// Sends every player of `game` the current question and scores.
+fn send_question_updates(game: &Game) {
+    let players = game.players.iter().map(|x| x.borrow()).collect::<Vec<_>>();
+
+    let standings = game::standings(&players.iter().map(|x| &**x).collect::<Vec<_>>());
+    for player in players.iter() {
+        player.send_question_update(&standings, &game.question);
+    }
+}
+
+/// Records the results of `game` and lets its players go. The caller removes
+/// the game.
+fn finish_game(database: &DatabasePool, game: &mut Game) {
+    record_results(database, game, GameEndReason::Finished);
+
+    // Older clients show the final score from a last question update
+    send_question_updates(game);
+    enter_phase(game, Phase::Finished);
+    for player in game.players.iter() {
+        player.borrow_mut().leave_game();
+    }
+}
+
+fn poll_game(state: &mut State) {
+    let grace_secs = state.config.game.reconnect_grace_secs;
+    let mut to_remove = Vec::new();
+
+    for game in state.games.values_mut() {
+        for player in game.players.iter() {
+            let mut player = player.borrow_mut();
+            let expired = player
+                .disconnected_at
+                .is_some_and(|at| at.elapsed().as_secs() >= grace_secs);
+
+            if expired && !player.forfeited {
+                println!("Player {} forfeited", player.id);
+                player.forfeited = true;
+            }
+        }
+
+        let remaining = game
+            .players
+            .iter()
+            .filter(|x| !x.borrow().forfeited)
+            .count();
+        if remaining < 2 {
+            record_results(&state.database, game, GameEndReason::Forfeit);
+            enter_phase(game, Phase::Finished);
+            for player in game.players.iter() {
+                player.borrow_mut().leave_game();
+            }
+
+            to_remove.push(game.id);
+            continue;
+        }
+
+        if !game.is_expired() {
+            continue;
+        }
+
+        match game.next_phase() {
+            Phase::QuestionOpen => {
+                let index = question::pick(
+                    &state.questions,
+                    &game.question_filter,
+                    game.round + 1,
+                    game.rules.rounds,
+                    &game.previous_questions,
+                );
+
+                // Only an empty question bank has nothing to pick, the match
+                // ends with the rounds played so far
+                let Some(index) = index else {
+                    println!("No question left for game {}, ending it", game.id);
+                    finish_game(&state.database, game);
+                    to_remove.push(game.id);
+                    continue;
+                };
+
+                // Games keep their copy when the question bank is reloaded
+                game.round += 1;
+                game.question = state.questions[index].clone();
+                game.previous_questions.insert(game.question.id);
+                for player in game.players.iter() {
+                    player.borrow_mut().answered = false;
                 }
 
-                p1.send_question_update(p1.points, p2.points, question);
-                p2.send_question_update(p2.points, p1.points, question);
+                enter_phase(game, Phase::QuestionOpen);
+                send_question_updates(game);
+            }
+
+            Phase::Finished => {
+                finish_game(&state.database, game);
+                to_remove.push(game.id);
             }
+
+            phase => enter_phase(game, phase),
         }
     }
 
-    for index in to_remove {
-        state.games.remove(index);
+    for id in to_remove {
+        state.games.remove(&id);
+    }
+}
+
+fn run_command(config: &Config, command: &Command) -> anyhow::Result<()> {
+    match command {
+        Command::Migrate { action } => database::run_migration_command(config, action),
+        Command::Questions { action } => database::run_question_command(config, action),
     }
 }
 
 fn main() -> ! {
     println!("Si Paling Cerdas Server!");
 
+    let cli = Cli::parse();
+    let config = Config::load(&cli).unwrap_or_else(|e| panic!("Cannot load config: {:#}", e));
+
+    if let Some(command) = &cli.command {
+        if let Err(error) = run_command(&config, command) {
+            eprintln!("{:#}", error);
+            std::process::exit(1);
+        }
+
+        std::process::exit(0);
+    }
+
     let enet = Enet::new().unwrap();
-    let address = Address::new(Ipv4Addr::UNSPECIFIED, 17091);
+    let address = Address::new(Ipv4Addr::UNSPECIFIED, config.server.port);
+    let max_peers = config.server.max_peers;
 
-    let mut state = State::new();
+    let mut state = State::new(config);
     let mut host = enet
         .create_host::<Rc<RefCell<Player>>>(
             Some(&address),
-            1024,
+            max_peers,
             ChannelLimit::Maximum,
             BandwidthLimit::Unlimited,
             BandwidthLimit::Unlimited,
@@ -508,7 +1356,84 @@ fn main() -> ! {
 
     loop {
         host_service(&mut host, &mut state);
-        send_packets(&mut host, &state);
+        poll_database(&mut state);
+        send_packets(&mut host, &mut state);
+        poll_matchmaking(&mut state);
         poll_game(&mut state);
+        poll_questions(&mut state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::Document;
+    use database::{memory::MemoryDatabase, PlayerRecord, Storage};
+    use player::tests::player;
+    use std::sync::mpsc;
+
+    fn game_ended(player: &Player) -> Document {
+        let packets = player.packets.borrow();
+        let (_, data) = packets.last().unwrap();
+
+        let document = bson::from_slice::<Document>(data).unwrap();
+        assert_eq!(
+            document.get_i32("PacketID").unwrap(),
+            protocol::PACKET_ID_GAME_ENDED as i32
+        );
+        document
+    }
+
+    #[test]
+    fn tie_is_recorded_as_a_draw() {
+        let mut storage = MemoryDatabase::new(&Config::default()).unwrap();
+        for rid in ["first", "second"] {
+            storage.insert_new_player(rid).unwrap();
+        }
+        let database = DatabasePool::new(vec![Box::new(storage)]);
+
+        let mut current = player(1, "Current", 30);
+        current.protocol_version = Some(protocol::PROTOCOL_VERSION);
+        // Legacy clients never say hello
+        let legacy = player(2, "Legacy", 30);
+
+        let game = Game::new(
+            1,
+            vec![
+                Rc::new(RefCell::new(current)),
+                Rc::new(RefCell::new(legacy)),
+            ],
+            true,
+            Config::default().rules.standard,
+            QuestionFilter::default(),
+        );
+        record_results(&database, &game, GameEndReason::Finished);
+
+        for (player, winner) in game.players.iter().zip(["", "-"]) {
+            let player = player.borrow();
+            assert_eq!(
+                (player.win_count, player.lose_count, player.draw_count),
+                (0, 0, 1)
+            );
+            // Scored 0.5 against an equal opponent
+            assert_eq!(player.rating, 0);
+
+            let packet = game_ended(&player);
+            assert_eq!(packet.get_i32("Outcome").unwrap(), GameOutcome::Draw as i32);
+            assert_eq!(packet.get_str("Winner").unwrap(), winner);
+            assert_eq!(packet.get_i32("Placement").unwrap(), 1);
+            assert_eq!(packet.get_i32("RatingDelta").unwrap(), 0);
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        database.execute(1, move |db| {
+            sender.send(db.load_player_by_id(1).unwrap()).unwrap();
+            None
+        });
+        let record: PlayerRecord = receiver.recv().unwrap();
+        assert_eq!(
+            (record.win_count, record.lose_count, record.draw_count),
+            (0, 0, 1)
+        );
     }
 }
@@ -0,0 +1,388 @@
+use crate::{
+    config::{MatchLength, MatchmakingConfig},
+    question::QuestionFilter,
+};
+use enet::PeerID;
+use std::{collections::HashMap, time::Instant};
+
+/// Weight of the latest wait time in the moving average behind the ETA.
+const WAIT_AVERAGE_WEIGHT: f64 = 0.2;
+
+/// Players are only paired with others queueing for the same mode.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueueMode {
+    pub competitive: bool,
+    pub length: MatchLength,
+    /// Players per match, more than 2 is a free-for-all
+    pub players: usize,
+    pub filter: QuestionFilter,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub peer_id: PeerID,
+    pub rating: i32,
+    pub joined_at: Instant,
+}
+
+impl QueueEntry {
+    /// How far the opponent's rating may be off, grows with the wait time.
+    pub fn rating_window(&self, config: &MatchmakingConfig) -> i32 {
+        let waited = self.joined_at.elapsed().as_secs() as i32;
+
+        config
+            .rating_window_growth_per_sec
+            .saturating_mul(waited)
+            .saturating_add(config.initial_rating_window)
+            .min(config.max_rating_window)
+    }
+
+    /// Whether both ratings are within both rating windows.
+    fn accepts(&self, other: &QueueEntry, config: &MatchmakingConfig) -> bool {
+        let difference = (self.rating - other.rating).abs();
+        difference <= self.rating_window(config) && difference <= other.rating_window(config)
+    }
+}
+
+/// Where a player is in the queue, sent to the client in the queue status.
+#[derive(Debug, Clone)]
+pub struct QueuePosition {
+    pub mode: QueueMode,
+    /// Starts at 1 for the player waiting the longest
+    pub position: usize,
+    pub queue_size: usize,
+    /// Seconds since the player joined the queue
+    pub elapsed_secs: u64,
+    pub eta_secs: Option<u64>,
+}
+
+/// One queue per mode, ordered by join time. Players are grouped with the
+/// one waiting the longest once the ratings of every two of them are within
+/// both of their rating windows.
+#[derive(Default)]
+pub struct Matchmaker {
+    queues: HashMap<QueueMode, Vec<QueueEntry>>,
+    /// Moving average of the seconds matched players waited, per mode
+    average_wait: HashMap<QueueMode, f64>,
+    status_sent_at: Option<Instant>,
+}
+
+impl Matchmaker {
+    /// Queueing again for the same mode keeps the spot, for the other mode
+    /// the player starts over at the end of that queue.
+    pub fn join(&mut self, mode: QueueMode, peer_id: PeerID, rating: i32) {
+        if self
+            .queues
+            .get(&mode)
+            .is_some_and(|queue| queue.iter().any(|x| x.peer_id == peer_id))
+        {
+            return;
+        }
+
+        self.leave(peer_id);
+        self.queues.entry(mode).or_default().push(QueueEntry {
+            peer_id,
+            rating,
+            joined_at: Instant::now(),
+        });
+    }
+
+    /// Returns false if the peer was not queued. Empty queues are dropped.
+    pub fn leave(&mut self, peer_id: PeerID) -> bool {
+        let mut found = false;
+        self.queues.retain(|_, queue| {
+            let len = queue.len();
+            queue.retain(|x| x.peer_id != peer_id);
+            found |= queue.len() != len;
+            !queue.is_empty()
+        });
+
+        found
+    }
+
+    /// Hands a queue spot over to another connection of the same player,
+    /// keeping the time already waited.
+    pub fn replace(&mut self, old: PeerID, new: PeerID) {
+        for entry in self.queues.values_mut().flatten() {
+            if entry.peer_id == old {
+                entry.peer_id = new;
+            }
+        }
+    }
+
+    pub fn position(&self, peer_id: PeerID) -> Option<QueuePosition> {
+        for (mode, queue) in self.queues.iter() {
+            let Some(index) = queue.iter().position(|x| x.peer_id == peer_id) else {
+                continue;
+            };
+
+            let waited = queue[index].joined_at.elapsed().as_secs_f64();
+            let eta_secs = self
+                .average_wait
+                .get(mode)
+                .map(|average| (average - waited).max(0.0).round() as u64);
+
+            return Some(QueuePosition {
+                mode: mode.clone(),
+                position: index + 1,
+                queue_size: queue.len(),
+                elapsed_secs: waited as u64,
+                eta_secs,
+            });
+        }
+
+        None
+    }
+
+    /// Every queued peer, once per `interval_secs`.
+    pub fn status_update_due(&mut self, interval_secs: u64) -> Vec<PeerID> {
+        if self
+            .status_sent_at
+            .is_some_and(|x| x.elapsed().as_secs() < interval_secs)
+        {
+            return Vec::new();
+        }
+
+        self.status_sent_at = Some(Instant::now());
+        self.queues.values().flatten().map(|x| x.peer_id).collect()
+    }
+
+    /// Removes and returns every group that can be matched right now.
+    /// Players waiting the longest are served first, each with the closest
+    /// ratings in range. The longest waiting player comes first.
+    pub fn find_matches(
+        &mut self,
+        config: &MatchmakingConfig,
+    ) -> Vec<(QueueMode, Vec<QueueEntry>)> {
+        let mut matches = Vec::new();
+
+        for (mode, queue) in self.queues.iter_mut() {
+            let mut i = 0;
+
+            while i < queue.len() {
+                let mut opponents = (i + 1..queue.len())
+                    .filter(|&index| queue[i].accepts(&queue[index], config))
+                    .collect::<Vec<_>>();
+                opponents.sort_by_key(|&index| (queue[index].rating - queue[i].rating).abs());
+
+                // Closest first, as long as everyone chosen accepts them too
+                let mut indices = vec![i];
+                for index in opponents {
+                    if indices.len() == mode.players {
+                        break;
+                    }
+
+                    if indices
+                        .iter()
+                        .all(|&x| queue[x].accepts(&queue[index], config))
+                    {
+                        indices.push(index);
+                    }
+                }
+
+                if indices.len() < mode.players {
+                    i += 1;
+                    continue;
+                }
+
+                // Remove from the back so the other indices stay valid
+                indices.sort_unstable_by(|a, b| b.cmp(a));
+                let mut entries = indices
+                    .into_iter()
+                    .map(|index| queue.remove(index))
+                    .collect::<Vec<_>>();
+                entries.reverse();
+
+                for entry in entries.iter() {
+                    let waited = entry.joined_at.elapsed().as_secs_f64();
+                    self.average_wait
+                        .entry(mode.clone())
+                        .and_modify(|x| *x += (waited - *x) * WAIT_AVERAGE_WEIGHT)
+                        .or_insert(waited);
+                }
+
+                matches.push((mode.clone(), entries));
+            }
+        }
+
+        self.queues.retain(|_, queue| !queue.is_empty());
+
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::tests::{peer_id, peer_number};
+    use std::time::Duration;
+
+    fn mode(players: usize) -> QueueMode {
+        QueueMode {
+            competitive: true,
+            length: MatchLength::Standard,
+            players,
+            filter: QuestionFilter::default(),
+        }
+    }
+
+    fn entry(number: usize, rating: i32, waited_secs: u64) -> QueueEntry {
+        QueueEntry {
+            peer_id: peer_id(number),
+            rating,
+            joined_at: Instant::now() - Duration::from_secs(waited_secs),
+        }
+    }
+
+    /// Windows start at 100, grow by 10 every second and stop at 1000.
+    fn config() -> MatchmakingConfig {
+        MatchmakingConfig::default()
+    }
+
+    fn queued(mode: QueueMode, entries: Vec<QueueEntry>) -> Matchmaker {
+        let mut matchmaker = Matchmaker::default();
+        matchmaker.queues.insert(mode, entries);
+        matchmaker
+    }
+
+    fn peers(entries: &[QueueEntry]) -> Vec<usize> {
+        entries.iter().map(|x| peer_number(x.peer_id)).collect()
+    }
+
+    #[test]
+    fn rating_window_grows_until_the_maximum() {
+        assert_eq!(entry(1, 0, 0).rating_window(&config()), 100);
+        assert_eq!(entry(1, 0, 20).rating_window(&config()), 300);
+        assert_eq!(entry(1, 0, 1000).rating_window(&config()), 1000);
+    }
+
+    #[test]
+    fn close_ratings_are_matched() {
+        let mut matchmaker = queued(mode(2), vec![entry(1, 1000, 0), entry(2, 1050, 0)]);
+
+        let matches = matchmaker.find_matches(&config());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(peers(&matches[0].1), [1, 2]);
+        assert!(matchmaker.queues.is_empty());
+    }
+
+    #[test]
+    fn far_ratings_wait_for_the_window_to_grow() {
+        let mut fresh = queued(mode(2), vec![entry(1, 1000, 0), entry(2, 1250, 0)]);
+        assert!(fresh.find_matches(&config()).is_empty());
+
+        let mut waited = queued(mode(2), vec![entry(1, 1000, 20), entry(2, 1250, 20)]);
+        assert_eq!(waited.find_matches(&config()).len(), 1);
+    }
+
+    #[test]
+    fn both_windows_have_to_accept() {
+        // The first one would take anyone, the second one not yet
+        let mut matchmaker = queued(mode(2), vec![entry(1, 1000, 100), entry(2, 1500, 0)]);
+
+        assert!(matchmaker.find_matches(&config()).is_empty());
+        assert_eq!(matchmaker.queues[&mode(2)].len(), 2);
+    }
+
+    #[test]
+    fn longest_waiting_gets_the_closest_rating() {
+        let mut matchmaker = queued(
+            mode(2),
+            vec![entry(1, 1000, 60), entry(2, 1150, 10), entry(3, 1100, 5)],
+        );
+
+        let matches = matchmaker.find_matches(&config());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(peers(&matches[0].1), [1, 3]);
+        assert_eq!(peers(&matchmaker.queues[&mode(2)]), [2]);
+    }
+
+    #[test]
+    fn groups_in_one_pass_keep_the_right_players() {
+        // Removing 1 and 3 shifts 2 and 4 to the front of the queue
+        let mut matchmaker = queued(
+            mode(2),
+            vec![
+                entry(1, 0, 0),
+                entry(2, 1000, 0),
+                entry(3, 10, 0),
+                entry(4, 1010, 0),
+            ],
+        );
+
+        let matches = matchmaker.find_matches(&config());
+        let groups = matches.iter().map(|(_, x)| peers(x)).collect::<Vec<_>>();
+        assert_eq!(groups, [vec![1, 3], vec![2, 4]]);
+    }
+
+    #[test]
+    fn free_for_all_waits_for_a_full_group() {
+        let mut matchmaker = queued(
+            mode(3),
+            vec![
+                entry(1, 0, 30),
+                entry(2, 500, 30),
+                entry(3, 80, 0),
+                entry(4, 50, 0),
+            ],
+        );
+
+        let matches = matchmaker.find_matches(&config());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0.players, 3);
+        // In queue order, the longest waiting first
+        assert_eq!(peers(&matches[0].1), [1, 3, 4]);
+        assert_eq!(peers(&matchmaker.queues[&mode(3)]), [2]);
+
+        // One player short
+        let mut matchmaker = queued(mode(3), vec![entry(1, 0, 0), entry(2, 0, 0)]);
+        assert!(matchmaker.find_matches(&config()).is_empty());
+    }
+
+    #[test]
+    fn free_for_all_players_have_to_accept_each_other() {
+        // 2 and 3 are both within 1's window, but 200 apart from each other
+        let mut matchmaker = queued(
+            mode(3),
+            vec![entry(1, 1000, 0), entry(2, 900, 0), entry(3, 1100, 0)],
+        );
+        assert!(matchmaker.find_matches(&config()).is_empty());
+        assert_eq!(matchmaker.queues[&mode(3)].len(), 3);
+
+        // 4 is a bit further from 1 than 3, but close to 2 as well
+        matchmaker
+            .queues
+            .get_mut(&mode(3))
+            .unwrap()
+            .push(entry(4, 950, 0));
+        let matches = matchmaker.find_matches(&config());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(peers(&matches[0].1), [1, 2, 4]);
+        assert_eq!(peers(&matchmaker.queues[&mode(3)]), [3]);
+    }
+
+    #[test]
+    fn modes_are_matched_separately() {
+        let mut matchmaker = Matchmaker::default();
+        matchmaker.join(mode(2), peer_id(1), 1000);
+        matchmaker.join(mode(3), peer_id(2), 1000);
+
+        assert!(matchmaker.find_matches(&config()).is_empty());
+
+        // Queueing for another mode moves the player over
+        matchmaker.join(mode(2), peer_id(2), 1000);
+        assert_eq!(matchmaker.find_matches(&config()).len(), 1);
+        assert!(matchmaker.queues.is_empty());
+    }
+
+    #[test]
+    fn leaving_drops_the_empty_queue() {
+        let mut matchmaker = Matchmaker::default();
+        matchmaker.join(mode(2), peer_id(1), 1000);
+
+        assert!(matchmaker.leave(peer_id(1)));
+        assert!(!matchmaker.leave(peer_id(1)));
+        assert!(matchmaker.queues.is_empty());
+        assert!(matchmaker.position(peer_id(1)).is_none());
+    }
+}
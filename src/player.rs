@@ -1,26 +1,99 @@
 use crate::{
-    database::{FriendInfo, FriendRequestInfo, LeaderboardInfo},
-    protocol,
+    auth::{self, IssuedToken},
+    config::{MatchLength, MatchRules},
+    database::{
+        pool::Requester, FriendInfo, FriendRequestInfo, LeaderboardInfo, PlayerRank, PlayerRecord,
+    },
+    game::{Game, GameId, Phase, Standing},
+    matchmaking::QueuePosition,
+    protocol::{self, response, GameEndReason, GameOutcome, PacketError, Request, Response},
+    rating::{self, Glicko},
     state::{GameQuestion, PacketSent},
 };
-use bson::{doc, spec::BinarySubtype, Array, Binary, Bson, Document};
 use enet::PeerID;
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, time::Instant};
+
+/// Where a connection is in its lifetime, decides which packets it may send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    Connected,
+    /// The authentication query is running
+    Authenticating,
+    Authenticated,
+    InQueue,
+    /// Hosting a private lobby or waiting for a challenged friend
+    InLobby,
+    InGame,
+}
+
+impl SessionState {
+    pub fn allows(self, request: &Request) -> bool {
+        match request {
+            Request::Hello(_) | Request::Authentication(_) => self == Self::Connected,
+            Request::AddQueue(_) | Request::CancelQueue | Request::FetchQueueStatus => {
+                matches!(self, Self::Authenticated | Self::InQueue)
+            }
+            Request::CreateLobby(_)
+            | Request::JoinLobby(_)
+            | Request::ChallengeFriend(_)
+            | Request::AcceptChallenge(_) => self == Self::Authenticated,
+            Request::LeaveLobby => self == Self::InLobby,
+            Request::QuestionAnswer(_) => self == Self::InGame,
+            Request::FetchLeaderboard
+            | Request::ChangeProfilePicture(_)
+            | Request::UpdateName(_)
+            | Request::FetchFriends
+            | Request::FetchFriendRequests
+            | Request::AcceptFriendRequest(_)
+            | Request::DeclineFriendRequest(_)
+            | Request::SearchName(_)
+            | Request::AddFriendRequest(_)
+            | Request::RegisterCredentials(_)
+            | Request::RefreshToken
+            | Request::RevokeTokens(_)
+            | Request::DeclineChallenge(_) => self.is_authenticated(),
+        }
+    }
+
+    pub fn is_authenticated(self) -> bool {
+        matches!(
+            self,
+            Self::Authenticated | Self::InQueue | Self::InLobby | Self::InGame
+        )
+    }
+}
 
 pub struct Player {
     pub peer_id: PeerID,
+    /// Unique per connection, unlike the peer ID
+    pub serial: u64,
+    pub session: SessionState,
 
     pub id: i32,
     pub rating: i32,
+    pub rating_deviation: f64,
+    pub rating_volatility: f64,
     pub points: i32,
     pub win_count: i32,
     pub lose_count: i32,
-    pub game_index: usize,
+    pub draw_count: i32,
+    /// The game the player is in, see `State::games`
+    pub game_id: Option<GameId>,
+    /// Set by `Hello`, or to the legacy version on authentication without it
+    pub protocol_version: Option<i32>,
 
     pub answered: bool,
+    /// Dropped out of the match and did not reconnect in time
+    pub forfeited: bool,
+    /// Set when the connection dropped during a match, the player stays in
+    /// the game until the reconnect grace period is over
+    pub disconnected_at: Option<Instant>,
 
     pub rid: String,
     pub name: String,
+    pub username: Option<String>,
+    /// Hash of the token handed out for this session, replaced on refresh
+    pub token_hash: Option<String>,
     pub pfp_ext: String,
 
     pub pfp_blob: Vec<u8>,
@@ -29,176 +102,596 @@ pub struct Player {
 }
 
 impl Player {
-    pub fn new(peer_id: PeerID, packets: Rc<RefCell<Vec<PacketSent>>>) -> Self {
+    pub fn new(peer_id: PeerID, serial: u64, packets: Rc<RefCell<Vec<PacketSent>>>) -> Self {
         Self {
             id: 0,
             rating: 0,
+            rating_deviation: rating::DEFAULT_DEVIATION,
+            rating_volatility: rating::DEFAULT_VOLATILITY,
             points: 0,
             win_count: 0,
             lose_count: 0,
-            game_index: usize::MAX,
+            draw_count: 0,
+            game_id: None,
+            protocol_version: None,
 
             answered: false,
+            forfeited: false,
+            disconnected_at: None,
 
             rid: String::new(),
             name: String::new(),
+            username: None,
+            token_hash: None,
             pfp_ext: String::new(),
 
             pfp_blob: Vec::new(),
 
             peer_id,
+            serial,
             packets,
+            session: SessionState::Connected,
         }
     }
 
-    pub fn send_packet(&self, bson: Document) {
-        let mut packets = self.packets.borrow_mut();
-        packets.push((self.peer_id, bson::to_vec(&bson).unwrap()));
+    pub fn load_record(&mut self, record: PlayerRecord) {
+        self.id = record.id;
+        self.rid = record.rid;
+        self.name = record.name;
+        self.rating = record.rating;
+        self.rating_deviation = record.rating_deviation;
+        self.rating_volatility = record.rating_volatility;
+        self.win_count = record.win_count;
+        self.lose_count = record.lose_count;
+        self.draw_count = record.draw_count;
+        self.pfp_blob = record.pfp_blob;
+        self.pfp_ext = record.pfp_ext;
+        self.username = record.username;
     }
 
-    pub fn send_auth_response(&self, name: &str, pfp_blob: &[u8], pfp_ext: &str, error: bool) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_AUTHENTICATION,
-            "Name": name,
-            "Error": error,
-            "ProfilePicture": Binary { subtype: BinarySubtype::Generic, bytes: pfp_blob.to_vec() },
-            "ProfilePictureExtension": pfp_ext
-        })
+    /// Identifies this connection in the database jobs it queues.
+    pub fn requester(&self) -> Requester {
+        Requester {
+            peer_id: self.peer_id,
+            serial: self.serial,
+        }
     }
 
-    pub fn send_match_notify(&self, name: &str, pfp_blob: &[u8], pfp_ext: &str) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_ADD_QUEUE,
-            "Name": name,
-            "ProfilePicture": Binary { subtype: BinarySubtype::Generic, bytes: pfp_blob.to_vec() },
-            "ProfilePictureExtension": pfp_ext
-        })
+    /// Clears the per match state once the player is out of its game.
+    pub fn leave_game(&mut self) {
+        self.session = SessionState::Authenticated;
+        self.game_id = None;
+        self.points = 0;
+        self.answered = false;
+        self.forfeited = false;
     }
 
-    pub fn send_question_update(&self, points: i32, enemy_points: i32, question: &GameQuestion) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_UPDATE_QUESTION,
-            "Points": points,
-            "Question": question.question.as_str(),
-            "EnemyPoints": enemy_points,
-            "AnswerOption1": question.answer_option_1.as_str(),
-            "AnswerOption2": question.answer_option_2.as_str(),
-            "AnswerOption3": question.answer_option_3.as_str(),
-            "AnswerOption4": question.answer_option_4.as_str(),
-        })
+    pub fn glicko(&self) -> Glicko {
+        Glicko {
+            rating: self.rating as f64,
+            deviation: self.rating_deviation,
+            volatility: self.rating_volatility,
+        }
     }
 
-    pub fn send_answer(&self, answer_index: i32) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_QUESTION_ANSWER,
-            "AnswerIndex": answer_index
-        })
-    }
+    /// Applies the outcome of a competitive match, returns the rating delta.
+    pub fn rate(&mut self, results: &[(Glicko, f64)]) -> i32 {
+        let rated = self.glicko().rate(results);
+        let delta = rated.rating.round() as i32 - self.rating;
 
-    pub fn send_update_name(&self, name: &str, error: bool) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_UPDATE_NAME,
-            "Name": name,
-            "Error": error
-        })
+        self.rating += delta;
+        self.rating_deviation = rated.deviation;
+        self.rating_volatility = rated.volatility;
+
+        delta
     }
 
-    pub fn send_game_ended(&self, winner: &str) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_GAME_ENDED,
-            "Winner": winner
-        })
+    pub fn rank(&self) -> PlayerRank {
+        PlayerRank {
+            id: self.id,
+            rating: self.rating,
+            rating_deviation: self.rating_deviation,
+            rating_volatility: self.rating_volatility,
+            win_count: self.win_count,
+            lose_count: self.lose_count,
+            draw_count: self.draw_count,
+        }
     }
 
-    pub fn send_friends(&self, friends: Vec<FriendInfo>) {
-        let mut array = Array::new();
+    pub fn send(&self, response: Response) {
+        // The peer ID may already belong to another connection
+        if self.disconnected_at.is_some() {
+            return;
+        }
 
-        for info in friends {
-            let pfp = Binary {
-                subtype: BinarySubtype::Generic,
-                bytes: info.pfp,
-            };
+        let data = match response.encode() {
+            Ok(data) => data,
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        };
 
-            let mut value = Document::new();
-            value.insert("ID", info.id);
-            value.insert("Name", info.name);
-            value.insert("ProfilePicture", pfp);
-            value.insert("ProfilePictureExtension", info.pfp_ext);
+        self.packets.borrow_mut().push((self.peer_id, data));
+    }
 
-            array.push(Bson::Document(value));
+    pub fn send_hello(&self, accepted: bool, message: &str) {
+        self.send(Response::Hello(response::HelloResponse {
+            accepted,
+            protocol_version: protocol::PROTOCOL_VERSION,
+            min_protocol_version: protocol::MIN_PROTOCOL_VERSION,
+            capabilities: protocol::CAPABILITIES
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            message: message.to_string(),
+        }))
+    }
+
+    /// Reports a refused request, unless the client predates the error packet.
+    pub fn send_error(&self, packet_id: Option<u32>, error: &PacketError) {
+        let version = self
+            .protocol_version
+            .unwrap_or(protocol::LEGACY_PROTOCOL_VERSION);
+
+        if version <= protocol::LEGACY_PROTOCOL_VERSION {
+            return;
         }
 
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_FETCH_FRIENDS,
-            "Friends": array
-        })
+        self.send(Response::Error(response::ErrorResponse {
+            code: error.code as i32,
+            request_packet_id: packet_id.map_or(-1, |x| x as i32),
+            message: error.message.clone(),
+        }))
     }
 
-    pub fn send_friend_requests(&self, friend_requests: Vec<FriendRequestInfo>) {
-        let mut array = Array::new();
+    pub fn send_kicked(&self, reason: &str) {
+        self.send(Response::Kicked(response::Kicked {
+            reason: reason.to_string(),
+        }))
+    }
 
-        for info in friend_requests {
-            let pfp = Binary {
-                subtype: BinarySubtype::Generic,
-                bytes: info.pfp,
-            };
+    pub fn send_opponent_status(&self, name: &str, connected: bool, grace_secs: u64) {
+        self.send(Response::OpponentStatus(response::OpponentStatus {
+            name: name.to_string(),
+            connected,
+            grace_secs: grace_secs as i64,
+        }))
+    }
 
-            let mut value = Document::new();
-            value.insert("ID", info.id);
-            value.insert("Name", info.name);
-            value.insert("ProfilePicture", pfp);
-            value.insert("ProfilePictureExtension", info.pfp_ext);
+    /// `None` tells the client it is no longer queued.
+    pub fn send_queue_status(&self, position: Option<QueuePosition>) {
+        let status = match position {
+            Some(position) => response::QueueStatus {
+                queued: true,
+                competitive: position.mode.competitive,
+                position: position.position as i32,
+                queue_size: position.queue_size as i32,
+                elapsed_secs: position.elapsed_secs as i32,
+                eta_secs: position.eta_secs.map_or(-1, |x| x as i32),
+            },
+            None => response::QueueStatus {
+                queued: false,
+                competitive: false,
+                position: 0,
+                queue_size: 0,
+                elapsed_secs: 0,
+                eta_secs: -1,
+            },
+        };
+
+        self.send(Response::QueueStatus(status))
+    }
 
-            array.push(Bson::Document(value));
-        }
+    pub fn send_lobby_created(&self, code: &str, length: MatchLength, capacity: usize) {
+        self.send(Response::LobbyCreated(response::LobbyCreated {
+            code: code.to_string(),
+            length,
+            player_count: capacity as i32,
+        }))
+    }
 
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_FETCH_FRIEND_REQUESTS,
-            "FriendRequests": array
-        })
+    pub fn send_lobby_updated(&self, code: &str, players: &[String], capacity: usize) {
+        self.send(Response::LobbyUpdated(response::LobbyUpdated {
+            code: code.to_string(),
+            players: players.to_vec(),
+            player_count: capacity as i32,
+        }))
+    }
+
+    pub fn send_lobby_closed(&self, code: &str, reason: &str) {
+        self.send(Response::LobbyClosed(response::LobbyClosed {
+            code: code.to_string(),
+            reason: reason.to_string(),
+        }))
+    }
+
+    pub fn send_challenge(&self, challenger: &Player, code: &str, length: MatchLength) {
+        self.send(Response::ChallengeReceived(response::ChallengeReceived {
+            id: challenger.id,
+            name: challenger.name.clone(),
+            profile_picture: challenger.pfp_blob.clone(),
+            profile_picture_extension: challenger.pfp_ext.clone(),
+            code: code.to_string(),
+            length,
+        }))
+    }
+
+    pub fn send_auth_response(&self, token: &IssuedToken) {
+        self.send(Response::Authentication(response::AuthResponse {
+            name: self.name.clone(),
+            error: false,
+            profile_picture: self.pfp_blob.clone(),
+            profile_picture_extension: self.pfp_ext.clone(),
+            username: self.username.clone().unwrap_or_default(),
+            token: token.token.clone(),
+            token_expires_at: token.expires_at,
+        }))
+    }
+
+    pub fn send_auth_failed(&self) {
+        self.send(Response::Authentication(response::AuthResponse {
+            name: String::new(),
+            error: true,
+            profile_picture: Vec::new(),
+            profile_picture_extension: String::new(),
+            username: String::new(),
+            token: String::new(),
+            token_expires_at: 0,
+        }))
+    }
+
+    pub fn send_credentials_registered(&self, username: &str, error: bool) {
+        self.send(Response::CredentialsRegistered(
+            response::CredentialsRegistered {
+                username: username.to_string(),
+                error,
+            },
+        ))
+    }
+
+    pub fn send_token_refreshed(&self, token: &IssuedToken) {
+        self.send(Response::TokenRefreshed(response::TokenRefreshed {
+            token: token.token.clone(),
+            token_expires_at: token.expires_at,
+        }))
+    }
+
+    pub fn send_tokens_revoked(&self, count: usize) {
+        self.send(Response::TokensRevoked(response::TokensRevoked {
+            count: count as i32,
+        }))
+    }
+
+    /// The first opponent is also described by the top level fields, for
+    /// clients that only know two player matches.
+    pub fn send_match_notify(&self, opponents: &[&Player], rules: &MatchRules) {
+        let opponents = opponents
+            .iter()
+            .map(|x| response::Opponent {
+                name: x.name.clone(),
+                profile_picture: x.pfp_blob.clone(),
+                profile_picture_extension: x.pfp_ext.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let Some(first) = opponents.first() else {
+            return;
+        };
+
+        self.send(Response::MatchFound(response::MatchFound {
+            name: first.name.clone(),
+            profile_picture: first.profile_picture.clone(),
+            profile_picture_extension: first.profile_picture_extension.clone(),
+            opponents,
+            rounds: rules.rounds,
+            countdown_secs: rules.countdown_secs as i32,
+            stage_secs: (rules.question_secs + rules.reveal_secs + rules.intermission_secs) as i32,
+            question_secs: rules.question_secs as i32,
+            reveal_secs: rules.reveal_secs as i32,
+            intermission_secs: rules.intermission_secs as i32,
+        }))
+    }
+
+    pub fn send_phase_changed(&self, game: &Game) {
+        let server_time_ms = auth::unix_time_ms();
+        let deadline_ms = if game.phase == Phase::Finished {
+            0
+        } else {
+            let remaining = game.deadline.saturating_duration_since(Instant::now());
+            server_time_ms + remaining.as_millis() as i64
+        };
+
+        self.send(Response::PhaseChanged(response::PhaseChanged {
+            phase: game.phase as i32,
+            round: game.round,
+            rounds: game.rules.rounds,
+            deadline_ms,
+            server_time_ms,
+        }))
+    }
+
+    /// `EnemyPoints` is the best score among the opponents.
+    pub fn send_question_update(&self, standings: &[Standing], question: &GameQuestion) {
+        let enemy_points = standings
+            .iter()
+            .filter(|x| x.id != self.id)
+            .map(|x| x.points)
+            .max()
+            .unwrap_or(0);
+
+        self.send(Response::QuestionUpdate(response::QuestionUpdate {
+            points: self.points,
+            question: question.question.clone(),
+            enemy_points,
+            scoreboard: scoreboard(standings),
+            answer_option1: question.answer_option_1.clone(),
+            answer_option2: question.answer_option_2.clone(),
+            answer_option3: question.answer_option_3.clone(),
+            answer_option4: question.answer_option_4.clone(),
+            category: question.category.clone(),
+            difficulty: question.difficulty,
+        }))
+    }
+
+    pub fn send_answer(&self, answer_index: i32) {
+        self.send(Response::Answer(response::Answer { answer_index }))
+    }
+
+    pub fn send_update_name(&self, name: &str, error: bool) {
+        self.send(Response::NameUpdated(response::NameUpdated {
+            name: name.to_string(),
+            error,
+        }))
+    }
+
+    /// `winner` is the winner's name, empty on a draw.
+    pub fn send_game_ended(
+        &self,
+        outcome: GameOutcome,
+        winner: &str,
+        reason: GameEndReason,
+        rating_delta: i32,
+        standings: &[Standing],
+    ) {
+        let version = self
+            .protocol_version
+            .unwrap_or(protocol::LEGACY_PROTOCOL_VERSION);
+
+        let winner = if outcome == GameOutcome::Draw && version <= protocol::LEGACY_PROTOCOL_VERSION
+        {
+            "-"
+        } else {
+            winner
+        };
+
+        self.send(Response::GameEnded(response::GameEnded {
+            outcome: outcome as i32,
+            winner: winner.to_string(),
+            reason: reason as i32,
+            rating: self.rating,
+            rating_delta,
+            placement: standings
+                .iter()
+                .find(|x| x.id == self.id)
+                .map_or(0, |x| x.placement),
+            scoreboard: scoreboard(standings),
+        }))
+    }
+
+    pub fn send_friends(&self, friends: Vec<FriendInfo>) {
+        let friends = friends
+            .into_iter()
+            .map(|info| response::FriendEntry {
+                id: info.id,
+                name: info.name,
+                profile_picture: info.pfp,
+                profile_picture_extension: info.pfp_ext,
+            })
+            .collect();
+
+        self.send(Response::Friends(response::Friends { friends }))
+    }
+
+    pub fn send_friend_requests(&self, friend_requests: Vec<FriendRequestInfo>) {
+        let friend_requests = friend_requests
+            .into_iter()
+            .map(|info| response::FriendEntry {
+                id: info.id,
+                name: info.name,
+                profile_picture: info.pfp,
+                profile_picture_extension: info.pfp_ext,
+            })
+            .collect();
+
+        self.send(Response::FriendRequests(response::FriendRequests {
+            friend_requests,
+        }))
     }
 
     pub fn send_search_name(&self, found: bool, name: &str, pfp_blob: &[u8], pfp_ext: &str) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_SEARCH_NAME,
-            "Name": name,
-            "Found": found,
-            "ProfilePicture": Binary { subtype: BinarySubtype::Generic, bytes: pfp_blob.to_vec() },
-            "ProfilePictureExtension": pfp_ext
-        })
+        self.send(Response::SearchName(response::SearchResult {
+            name: name.to_string(),
+            found,
+            profile_picture: pfp_blob.to_vec(),
+            profile_picture_extension: pfp_ext.to_string(),
+        }))
     }
 
     pub fn send_leaderboard(&self, leaderboard: Vec<LeaderboardInfo>) {
-        let mut array = Array::new();
-
-        for info in leaderboard {
-            let pfp = Binary {
-                subtype: BinarySubtype::Generic,
-                bytes: info.pfp,
-            };
-
-            let mut value = Document::new();
-            value.insert("Win", info.win);
-            value.insert("Lose", info.lose);
-            value.insert("Name", info.name);
-            value.insert("Rating", info.rating);
-            value.insert("ProfilePicture", pfp);
-            value.insert("ProfilePictureExtension", info.pfp_ext);
-
-            array.push(Bson::Document(value));
-        }
-
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_FETCH_LEADERBOARD,
-            "Leaderboard": array
-        });
+        let leaderboard = leaderboard
+            .into_iter()
+            .map(|info| response::LeaderboardEntry {
+                win: info.win,
+                lose: info.lose,
+                draw: info.draw,
+                name: info.name,
+                rating: info.rating,
+                profile_picture: info.pfp,
+                profile_picture_extension: info.pfp_ext,
+            })
+            .collect();
+
+        self.send(Response::Leaderboard(response::Leaderboard { leaderboard }));
     }
 
     pub fn send_change_profile_picture(&self, pfp_blob: &[u8], pfp_ext: &str) {
-        self.send_packet(doc! {
-            "PacketID": protocol::PACKET_ID_CHANGE_PROFILE_PICTURE,
-            "ProfilePicture": Binary { subtype: BinarySubtype::Generic, bytes: pfp_blob.to_vec() },
-            "ProfilePictureExtension": pfp_ext
+        self.send(Response::ProfilePictureChanged(response::ProfilePicture {
+            profile_picture: pfp_blob.to_vec(),
+            profile_picture_extension: pfp_ext.to_string(),
+        }))
+    }
+}
+
+fn scoreboard(standings: &[Standing]) -> Vec<response::ScoreEntry> {
+    standings
+        .iter()
+        .map(|x| response::ScoreEntry {
+            name: x.name.clone(),
+            points: x.points,
+            placement: x.placement,
         })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use bson::doc;
+    use enet::{Address, BandwidthLimit, ChannelLimit, Enet};
+    use std::{net::Ipv4Addr, sync::OnceLock};
+
+    const PEERS: usize = 8;
+
+    thread_local! {
+        /// Connections of a client host, they never have to reach the server.
+        static PEER_IDS: Vec<PeerID> = {
+            // ENet can only be initialized once per process
+            static ENET: OnceLock<Enet> = OnceLock::new();
+            let enet = ENET.get_or_init(|| Enet::new().unwrap());
+
+            let mut host = enet
+                .create_host::<()>(
+                    None,
+                    PEERS,
+                    ChannelLimit::Maximum,
+                    BandwidthLimit::Unlimited,
+                    BandwidthLimit::Unlimited,
+                )
+                .unwrap();
+            let address = Address::new(Ipv4Addr::LOCALHOST, 9);
+
+            (0..PEERS)
+                .map(|_| host.connect(&address, 1, 0).unwrap())
+                .collect()
+        };
+    }
+
+    /// A distinct peer ID for every number below 8.
+    pub fn peer_id(number: usize) -> PeerID {
+        PEER_IDS.with(|x| x[number])
+    }
+
+    /// The number `peer_id` was created with.
+    pub fn peer_number(peer_id: PeerID) -> usize {
+        PEER_IDS.with(|x| x.iter().position(|id| *id == peer_id).unwrap())
+    }
+
+    /// An authenticated player, `id` also picks the peer ID.
+    pub fn player(id: i32, name: &str, points: i32) -> Player {
+        let mut player = Player::new(peer_id(id as usize), id as u64, Rc::default());
+        player.session = SessionState::Authenticated;
+        player.id = id;
+        player.name = name.to_string();
+        player.points = points;
+        player
+    }
+
+    fn id(packet_id: u32) -> i32 {
+        packet_id as i32
+    }
+
+    fn request(document: bson::Document) -> Request {
+        Request::decode(&bson::to_vec(&document).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn session_allows_only_its_requests() {
+        use protocol::*;
+        use SessionState::*;
+
+        let states = [
+            Connected,
+            Authenticating,
+            Authenticated,
+            InQueue,
+            InLobby,
+            InGame,
+        ];
+        let logged_in = &[Authenticated, InQueue, InLobby, InGame][..];
+
+        // Nothing while authenticating, cancelling the queue would end up
+        // authenticated before the login finished
+        let table = [
+            (
+                doc! { "PacketID": id(PACKET_ID_HELLO), "ProtocolVersion": 2 },
+                &[Connected][..],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_AUTHENTICATION), "RID": "rid" },
+                &[Connected],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_ADD_QUEUE), "Competitive": true },
+                &[Authenticated, InQueue],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_CANCEL_QUEUE) },
+                &[Authenticated, InQueue],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_QUEUE_STATUS) },
+                &[Authenticated, InQueue],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_CREATE_LOBBY) },
+                &[Authenticated],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_JOIN_LOBBY), "Code": "ABCDEF" },
+                &[Authenticated],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_CHALLENGE_FRIEND), "ID": 2 },
+                &[Authenticated],
+            ),
+            (doc! { "PacketID": id(PACKET_ID_LEAVE_LOBBY) }, &[InLobby]),
+            (
+                doc! { "PacketID": id(PACKET_ID_QUESTION_ANSWER), "AnswerIndex": 0 },
+                &[InGame],
+            ),
+            (
+                doc! { "PacketID": id(PACKET_ID_FETCH_LEADERBOARD) },
+                logged_in,
+            ),
+            (doc! { "PacketID": id(PACKET_ID_REFRESH_TOKEN) }, logged_in),
+            (
+                doc! { "PacketID": id(PACKET_ID_DECLINE_CHALLENGE), "Code": "ABCDEF" },
+                logged_in,
+            ),
+        ];
+
+        for (document, allowed) in table {
+            let request = request(document);
+            for state in states {
+                assert_eq!(
+                    state.allows(&request),
+                    allowed.contains(&state),
+                    "{:?} in {:?}",
+                    request,
+                    state
+                );
+            }
+        }
     }
 }
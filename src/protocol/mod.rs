@@ -0,0 +1,216 @@
+pub mod request;
+pub mod response;
+
+pub use request::Request;
+pub use response::Response;
+
+use std::fmt;
+
+pub const PACKET_ID_AUTHENTICATION: u32 = 0;
+pub const PACKET_ID_ADD_QUEUE: u32 = 1;
+pub const PACKET_ID_UPDATE_QUESTION: u32 = 2;
+pub const PACKET_ID_QUESTION_ANSWER: u32 = 3;
+pub const PACKET_ID_FETCH_LEADERBOARD: u32 = 4;
+pub const PACKET_ID_CHANGE_PROFILE_PICTURE: u32 = 5;
+pub const PACKET_ID_UPDATE_NAME: u32 = 6;
+pub const PACKET_ID_GAME_ENDED: u32 = 7;
+pub const PACKET_ID_FETCH_FRIENDS: u32 = 8;
+pub const PACKET_ID_FETCH_FRIEND_REQUESTS: u32 = 9;
+pub const PACKET_ID_ACCEPT_FRIEND_REQUEST: u32 = 10;
+pub const PACKET_ID_DECLINE_FRIEND_REQUEST: u32 = 11;
+pub const PACKET_ID_SEARCH_NAME: u32 = 12;
+pub const PACKET_ID_ADD_FRIEND_REQUEST: u32 = 13;
+pub const PACKET_ID_HELLO: u32 = 14;
+pub const PACKET_ID_ERROR: u32 = 15;
+pub const PACKET_ID_REGISTER_CREDENTIALS: u32 = 16;
+pub const PACKET_ID_REFRESH_TOKEN: u32 = 17;
+pub const PACKET_ID_REVOKE_TOKENS: u32 = 18;
+pub const PACKET_ID_KICKED: u32 = 19;
+pub const PACKET_ID_OPPONENT_STATUS: u32 = 20;
+pub const PACKET_ID_QUEUE_STATUS: u32 = 21;
+pub const PACKET_ID_CANCEL_QUEUE: u32 = 22;
+pub const PACKET_ID_CREATE_LOBBY: u32 = 23;
+pub const PACKET_ID_JOIN_LOBBY: u32 = 24;
+pub const PACKET_ID_LEAVE_LOBBY: u32 = 25;
+pub const PACKET_ID_CHALLENGE_FRIEND: u32 = 26;
+pub const PACKET_ID_ACCEPT_CHALLENGE: u32 = 27;
+pub const PACKET_ID_DECLINE_CHALLENGE: u32 = 28;
+pub const PACKET_ID_PHASE_CHANGED: u32 = 29;
+
+/// Version of the packet format spoken by this server. Bump it on every
+/// change an older client cannot cope with.
+pub const PROTOCOL_VERSION: i32 = 2;
+/// Oldest client protocol the server still accepts, older clients are told to
+/// update.
+pub const MIN_PROTOCOL_VERSION: i32 = 1;
+/// Clients from before the hello packet authenticate straight away and are
+/// assumed to speak this version. They don't know the error packet either.
+pub const LEGACY_PROTOCOL_VERSION: i32 = 1;
+
+/// Optional features advertised to the client in the hello response.
+pub const CAPABILITIES: &[&str] = &[
+    "accounts",
+    "competitive_queue",
+    "error_packet",
+    "free_for_all",
+    "friends",
+    "leaderboard",
+    "lobbies",
+    "match_lengths",
+    "match_phases",
+    "matchmaking",
+    "profile_picture",
+    "question_categories",
+    "reconnect",
+    "search_name",
+];
+
+/// Sent in the error packet so the client can tell what went wrong without
+/// parsing the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ErrorCode {
+    /// Not a BSON document with a `PacketID`
+    InvalidPacket = 1,
+    UnknownPacket = 2,
+    /// A field is missing or has the wrong type
+    MalformedPacket = 3,
+    /// The packet is valid but not right now, e.g. answering outside a game
+    InvalidState = 4,
+    /// A field has a value the server refuses
+    InvalidArgument = 5,
+    /// Storage failed, the request may be retried later
+    DatabaseError = 6,
+    /// Something a well behaved client never sends
+    Abuse = 7,
+    /// Wrong password, or an invalid, expired or revoked token
+    Unauthorized = 8,
+}
+
+impl ErrorCode {
+    /// Only protocol abuse ends the connection, everything else is reported
+    /// and the client may carry on.
+    pub fn disconnects(self) -> bool {
+        matches!(self, Self::InvalidPacket | Self::Abuse)
+    }
+}
+
+/// A request the server refused, reported to the client with `send_error`.
+#[derive(Debug)]
+pub struct PacketError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl PacketError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+/// Why a match ended, sent in the game ended packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum GameEndReason {
+    /// Every round was played
+    Finished = 0,
+    /// Everyone but one player dropped out and did not reconnect in time
+    Forfeit = 1,
+}
+
+/// How a match ended for the player receiving the game ended packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum GameOutcome {
+    Win = 0,
+    Loss = 1,
+    Draw = 2,
+}
+
+impl GameOutcome {
+    /// Score used by the rating system.
+    pub fn score(self) -> f64 {
+        match self {
+            Self::Win => 1.0,
+            Self::Loss => 0.0,
+            Self::Draw => 0.5,
+        }
+    }
+}
+
+/// Why a packet could not be decoded or encoded.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The packet is not a BSON document
+    InvalidBson(bson::de::Error),
+    /// The document has no Int32 `PacketID`
+    MissingPacketId,
+    /// `PacketID` is not a packet the client may send
+    UnknownPacketId(i32),
+    /// A field of the packet is missing or has the wrong type
+    InvalidField {
+        packet_id: u32,
+        error: bson::de::Error,
+    },
+    Encode(bson::ser::Error),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBson(error) => write!(f, "Invalid bson: {}", error),
+            Self::MissingPacketId => write!(f, "PacketID not found"),
+            Self::UnknownPacketId(id) => write!(f, "Unknown PacketID {}", id),
+            Self::InvalidField { packet_id, error } => {
+                write!(f, "Invalid packet {}: {}", packet_id, error)
+            }
+            Self::Encode(error) => write!(f, "Cannot encode packet: {}", error),
+        }
+    }
+}
+
+impl ProtocolError {
+    /// The packet the error is about, if it got that far.
+    pub fn packet_id(&self) -> Option<u32> {
+        match self {
+            Self::UnknownPacketId(id) => Some(*id as u32),
+            Self::InvalidField { packet_id, .. } => Some(*packet_id),
+            Self::InvalidBson(_) | Self::MissingPacketId | Self::Encode(_) => None,
+        }
+    }
+}
+
+impl From<ProtocolError> for PacketError {
+    fn from(error: ProtocolError) -> Self {
+        let code = match error {
+            ProtocolError::InvalidBson(_) | ProtocolError::MissingPacketId => {
+                ErrorCode::InvalidPacket
+            }
+            ProtocolError::UnknownPacketId(_) => ErrorCode::UnknownPacket,
+            ProtocolError::InvalidField { .. } | ProtocolError::Encode(_) => {
+                ErrorCode::MalformedPacket
+            }
+        };
+
+        PacketError::new(code, error.to_string())
+    }
+}
+
+impl std::error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidBson(error) | Self::InvalidField { error, .. } => Some(error),
+            Self::Encode(error) => Some(error),
+            Self::MissingPacketId | Self::UnknownPacketId(_) => None,
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use super::*;
+use crate::{auth::Credentials, config::MatchLength, question::DifficultyCurve};
+use bson::Document;
+use serde::{de::DeserializeOwned, Deserialize};
+
+/// First packet of a client that knows about protocol versions, sent before
+/// `Authentication`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Hello {
+    pub protocol_version: i32,
+    /// Build of the client, only used for logging
+    #[serde(default)]
+    pub client_version: String,
+}
+
+/// Exactly one way of authenticating is used, in order of preference
+/// `Token`, `Username` with `Password`, then the guest `RID`.
+#[derive(Deserialize, Debug)]
+pub struct Authentication {
+    #[serde(rename = "RID", default)]
+    pub rid: Option<String>,
+    #[serde(rename = "Token", default)]
+    pub token: Option<String>,
+    #[serde(rename = "Username", default)]
+    pub username: Option<String>,
+    #[serde(rename = "Password", default)]
+    pub password: Option<String>,
+}
+
+impl Authentication {
+    pub fn into_credentials(self) -> Option<Credentials> {
+        if let Some(token) = self.token {
+            return Some(Credentials::Token { token });
+        }
+
+        if let (Some(username), Some(password)) = (self.username, self.password) {
+            return Some(Credentials::Password { username, password });
+        }
+
+        let rid = self.rid.filter(|x| !x.is_empty())?;
+        Some(Credentials::Guest { rid })
+    }
+}
+
+/// Lets a guest log in with a username and password from now on.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct RegisterCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct RevokeTokens {
+    /// Revoke the tokens of every device instead of only this session's
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AddQueue {
+    pub competitive: bool,
+    /// "quick", "standard" or "marathon"
+    #[serde(default)]
+    pub length: MatchLength,
+    /// Size of the match, more than 2 is a free-for-all
+    #[serde(default = "default_player_count")]
+    pub player_count: usize,
+    /// Only questions of this category, any if missing
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Language code of the questions, any if missing
+    #[serde(default)]
+    pub language: Option<String>,
+    /// "mixed" or "rising"
+    #[serde(default)]
+    pub difficulty_curve: DifficultyCurve,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuestionAnswer {
+    pub answer_index: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangeProfilePicture {
+    #[serde(rename = "ProfilePicture", with = "serde_bytes")]
+    pub pfp_blob: Vec<u8>,
+    #[serde(rename = "ProfilePictureExtension")]
+    pub extension: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct UpdateName {
+    pub name: String,
+}
+
+/// Another player targeted by a friend request packet.
+#[derive(Deserialize, Debug)]
+pub struct FriendTarget {
+    #[serde(rename = "ID")]
+    pub id: i32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SearchName {
+    pub name: String,
+}
+
+/// Opens a private lobby, answered with its join code.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct CreateLobby {
+    #[serde(default)]
+    pub length: MatchLength,
+    /// The match starts once this many players are in, host included
+    #[serde(default = "default_player_count")]
+    pub player_count: usize,
+    /// Only questions of this category, any if missing
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Language code of the questions, any if missing
+    #[serde(default)]
+    pub language: Option<String>,
+    /// "mixed" or "rising"
+    #[serde(default)]
+    pub difficulty_curve: DifficultyCurve,
+}
+
+/// Joining a lobby, or accepting or declining a challenge.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LobbyCode {
+    pub code: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ChallengeFriend {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    #[serde(default)]
+    pub length: MatchLength,
+}
+
+/// Every packet a client may send, decoded by `PacketID`.
+#[derive(Debug)]
+pub enum Request {
+    Hello(Hello),
+    Authentication(Authentication),
+    AddQueue(AddQueue),
+    QuestionAnswer(QuestionAnswer),
+    FetchLeaderboard,
+    ChangeProfilePicture(ChangeProfilePicture),
+    UpdateName(UpdateName),
+    FetchFriends,
+    FetchFriendRequests,
+    AcceptFriendRequest(FriendTarget),
+    DeclineFriendRequest(FriendTarget),
+    SearchName(SearchName),
+    AddFriendRequest(FriendTarget),
+    RegisterCredentials(RegisterCredentials),
+    RefreshToken,
+    RevokeTokens(RevokeTokens),
+    CancelQueue,
+    FetchQueueStatus,
+    CreateLobby(CreateLobby),
+    JoinLobby(LobbyCode),
+    LeaveLobby,
+    ChallengeFriend(ChallengeFriend),
+    AcceptChallenge(LobbyCode),
+    DeclineChallenge(LobbyCode),
+}
+
+impl Request {
+    pub fn packet_id(&self) -> u32 {
+        match self {
+            Self::Hello(_) => PACKET_ID_HELLO,
+            Self::Authentication(_) => PACKET_ID_AUTHENTICATION,
+            Self::AddQueue(_) => PACKET_ID_ADD_QUEUE,
+            Self::QuestionAnswer(_) => PACKET_ID_QUESTION_ANSWER,
+            Self::FetchLeaderboard => PACKET_ID_FETCH_LEADERBOARD,
+            Self::ChangeProfilePicture(_) => PACKET_ID_CHANGE_PROFILE_PICTURE,
+            Self::UpdateName(_) => PACKET_ID_UPDATE_NAME,
+            Self::FetchFriends => PACKET_ID_FETCH_FRIENDS,
+            Self::FetchFriendRequests => PACKET_ID_FETCH_FRIEND_REQUESTS,
+            Self::AcceptFriendRequest(_) => PACKET_ID_ACCEPT_FRIEND_REQUEST,
+            Self::DeclineFriendRequest(_) => PACKET_ID_DECLINE_FRIEND_REQUEST,
+            Self::SearchName(_) => PACKET_ID_SEARCH_NAME,
+            Self::AddFriendRequest(_) => PACKET_ID_ADD_FRIEND_REQUEST,
+            Self::RegisterCredentials(_) => PACKET_ID_REGISTER_CREDENTIALS,
+            Self::RefreshToken => PACKET_ID_REFRESH_TOKEN,
+            Self::RevokeTokens(_) => PACKET_ID_REVOKE_TOKENS,
+            Self::CancelQueue => PACKET_ID_CANCEL_QUEUE,
+            Self::FetchQueueStatus => PACKET_ID_QUEUE_STATUS,
+            Self::CreateLobby(_) => PACKET_ID_CREATE_LOBBY,
+            Self::JoinLobby(_) => PACKET_ID_JOIN_LOBBY,
+            Self::LeaveLobby => PACKET_ID_LEAVE_LOBBY,
+            Self::ChallengeFriend(_) => PACKET_ID_CHALLENGE_FRIEND,
+            Self::AcceptChallenge(_) => PACKET_ID_ACCEPT_CHALLENGE,
+            Self::DeclineChallenge(_) => PACKET_ID_DECLINE_CHALLENGE,
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, ProtocolError> {
+        let document = bson::from_slice::<Document>(data).map_err(ProtocolError::InvalidBson)?;
+        let id = document
+            .get_i32("PacketID")
+            .map_err(|_| ProtocolError::MissingPacketId)?;
+
+        let request = match id as u32 {
+            PACKET_ID_HELLO => Self::Hello(fields(document, id)?),
+            PACKET_ID_AUTHENTICATION => Self::Authentication(fields(document, id)?),
+            PACKET_ID_ADD_QUEUE => Self::AddQueue(fields(document, id)?),
+            PACKET_ID_QUESTION_ANSWER => Self::QuestionAnswer(fields(document, id)?),
+            PACKET_ID_FETCH_LEADERBOARD => Self::FetchLeaderboard,
+            PACKET_ID_CHANGE_PROFILE_PICTURE => Self::ChangeProfilePicture(fields(document, id)?),
+            PACKET_ID_UPDATE_NAME => Self::UpdateName(fields(document, id)?),
+            PACKET_ID_FETCH_FRIENDS => Self::FetchFriends,
+            PACKET_ID_FETCH_FRIEND_REQUESTS => Self::FetchFriendRequests,
+            PACKET_ID_ACCEPT_FRIEND_REQUEST => Self::AcceptFriendRequest(fields(document, id)?),
+            PACKET_ID_DECLINE_FRIEND_REQUEST => Self::DeclineFriendRequest(fields(document, id)?),
+            PACKET_ID_SEARCH_NAME => Self::SearchName(fields(document, id)?),
+            PACKET_ID_ADD_FRIEND_REQUEST => Self::AddFriendRequest(fields(document, id)?),
+            PACKET_ID_REGISTER_CREDENTIALS => Self::RegisterCredentials(fields(document, id)?),
+            PACKET_ID_REFRESH_TOKEN => Self::RefreshToken,
+            PACKET_ID_REVOKE_TOKENS => Self::RevokeTokens(fields(document, id)?),
+            PACKET_ID_CANCEL_QUEUE => Self::CancelQueue,
+            PACKET_ID_QUEUE_STATUS => Self::FetchQueueStatus,
+            PACKET_ID_CREATE_LOBBY => Self::CreateLobby(fields(document, id)?),
+            PACKET_ID_JOIN_LOBBY => Self::JoinLobby(fields(document, id)?),
+            PACKET_ID_LEAVE_LOBBY => Self::LeaveLobby,
+            PACKET_ID_CHALLENGE_FRIEND => Self::ChallengeFriend(fields(document, id)?),
+            PACKET_ID_ACCEPT_CHALLENGE => Self::AcceptChallenge(fields(document, id)?),
+            PACKET_ID_DECLINE_CHALLENGE => Self::DeclineChallenge(fields(document, id)?),
+            _ => return Err(ProtocolError::UnknownPacketId(id)),
+        };
+
+        Ok(request)
+    }
+}
+
+fn default_player_count() -> usize {
+    2
+}
+
+fn fields<T: DeserializeOwned>(document: Document, id: i32) -> Result<T, ProtocolError> {
+    bson::from_document(document).map_err(|error| ProtocolError::InvalidField {
+        packet_id: id as u32,
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn decode(document: Document) -> Result<Request, ProtocolError> {
+        Request::decode(&bson::to_vec(&document).unwrap())
+    }
+
+    fn error_code(error: ProtocolError) -> ErrorCode {
+        PacketError::from(error).code
+    }
+
+    #[test]
+    fn packet_with_fields_is_decoded() {
+        let request = decode(doc! { "PacketID": 3, "AnswerIndex": 2 }).unwrap();
+        let Request::QuestionAnswer(answer) = request else {
+            panic!("Decoded as {:?}", request);
+        };
+        assert_eq!(answer.answer_index, 2);
+
+        // Missing optional fields fall back to their defaults
+        let request =
+            decode(doc! { "PacketID": 1, "Competitive": true, "Category": "science" }).unwrap();
+        let Request::AddQueue(queue) = request else {
+            panic!("Decoded as {:?}", request);
+        };
+        assert!(queue.competitive);
+        assert_eq!(queue.length, MatchLength::Standard);
+        assert_eq!(queue.player_count, 2);
+        assert_eq!(queue.category.as_deref(), Some("science"));
+        assert_eq!(queue.language, None);
+        assert_eq!(queue.difficulty_curve, DifficultyCurve::Mixed);
+    }
+
+    #[test]
+    fn packet_without_fields_ignores_extra_ones() {
+        let request = decode(doc! { "PacketID": 4, "Page": 2 }).unwrap();
+        assert!(matches!(request, Request::FetchLeaderboard));
+        assert_eq!(request.packet_id(), PACKET_ID_FETCH_LEADERBOARD);
+    }
+
+    #[test]
+    fn unknown_packet_id_is_reported() {
+        let error = decode(doc! { "PacketID": 99 }).unwrap_err();
+        assert!(matches!(error, ProtocolError::UnknownPacketId(99)));
+        assert_eq!(error.packet_id(), Some(99));
+        assert_eq!(error_code(error), ErrorCode::UnknownPacket);
+
+        // Server to client only
+        let error = decode(doc! { "PacketID": PACKET_ID_GAME_ENDED as i32 }).unwrap_err();
+        assert!(matches!(error, ProtocolError::UnknownPacketId(_)));
+    }
+
+    #[test]
+    fn missing_or_wrongly_typed_field_is_malformed() {
+        let missing = decode(doc! { "PacketID": 3 }).unwrap_err();
+        let wrong_type = decode(doc! { "PacketID": 3, "AnswerIndex": "two" }).unwrap_err();
+
+        for error in [missing, wrong_type] {
+            assert!(matches!(
+                error,
+                ProtocolError::InvalidField { packet_id: 3, .. }
+            ));
+            assert_eq!(error_code(error), ErrorCode::MalformedPacket);
+        }
+
+        let error = decode(doc! { "PacketID": 1, "Length": "endless" }).unwrap_err();
+        assert_eq!(error_code(error), ErrorCode::MalformedPacket);
+    }
+
+    #[test]
+    fn packet_without_id_is_invalid() {
+        let missing = decode(doc! { "AnswerIndex": 2 }).unwrap_err();
+        let wrong_type = decode(doc! { "PacketID": "3" }).unwrap_err();
+
+        for error in [missing, wrong_type] {
+            assert!(matches!(error, ProtocolError::MissingPacketId));
+            assert_eq!(error.packet_id(), None);
+            assert_eq!(error_code(error), ErrorCode::InvalidPacket);
+        }
+
+        let error = Request::decode(b"not bson").unwrap_err();
+        assert!(matches!(error, ProtocolError::InvalidBson(_)));
+        assert!(error_code(error).disconnects());
+    }
+
+    #[test]
+    fn token_is_preferred_over_password_and_rid() {
+        let request = decode(doc! {
+            "PacketID": 0,
+            "RID": "rid",
+            "Username": "user",
+            "Password": "password",
+            "Token": "token",
+        })
+        .unwrap();
+        let Request::Authentication(authentication) = request else {
+            panic!("Decoded as {:?}", request);
+        };
+        assert!(matches!(
+            authentication.into_credentials(),
+            Some(Credentials::Token { .. })
+        ));
+
+        let Request::Authentication(authentication) =
+            decode(doc! { "PacketID": 0, "RID": "" }).unwrap()
+        else {
+            panic!("Not an authentication");
+        };
+        assert!(authentication.into_credentials().is_none());
+    }
+}
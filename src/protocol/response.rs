@@ -0,0 +1,376 @@
+use super::*;
+use crate::config::MatchLength;
+use bson::doc;
+use serde::Serialize;
+
+/// Answer to `Hello`. When `accepted` is false the client is too old, should
+/// show `message` and ask the user to update, and is disconnected.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct HelloResponse {
+    pub accepted: bool,
+    pub protocol_version: i32,
+    pub min_protocol_version: i32,
+    pub capabilities: Vec<String>,
+    pub message: String,
+}
+
+/// Reply to a request the server refused.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorResponse {
+    /// An `ErrorCode`
+    pub code: i32,
+    /// The refused packet, -1 if it could not be decoded
+    #[serde(rename = "RequestPacketID")]
+    pub request_packet_id: i32,
+    pub message: String,
+}
+
+/// An opponent dropped out of the match or came back.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct OpponentStatus {
+    pub name: String,
+    pub connected: bool,
+    /// Seconds the opponent has to reconnect before forfeiting the match
+    pub grace_secs: i64,
+}
+
+/// Sent when joining or leaving the matchmaking queue, on request and every
+/// `status_interval_secs` while queued.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct QueueStatus {
+    /// False once the player left the queue, the other fields are then 0
+    pub queued: bool,
+    pub competitive: bool,
+    /// Starts at 1 for the player waiting the longest
+    pub position: i32,
+    pub queue_size: i32,
+    /// Seconds since the player joined the queue
+    pub elapsed_secs: i32,
+    /// Estimated seconds until a match is found, -1 if unknown
+    pub eta_secs: i32,
+}
+
+/// Answer to `CreateLobby` and `ChallengeFriend`, the host waits until
+/// someone joins with `code`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LobbyCreated {
+    pub code: String,
+    pub length: MatchLength,
+    pub player_count: i32,
+}
+
+/// Sent to everyone in a lobby when a player joins or leaves.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LobbyUpdated {
+    pub code: String,
+    /// Host first
+    pub players: Vec<String>,
+    pub player_count: i32,
+}
+
+/// The lobby is gone without a match. Sent to the host, and to the
+/// challenged friend if the host withdraws.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LobbyClosed {
+    pub code: String,
+    pub reason: String,
+}
+
+/// A friend challenges the receiving player, answered with
+/// `AcceptChallenge` or `DeclineChallenge`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ChallengeReceived {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    pub name: String,
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+    pub code: String,
+    pub length: MatchLength,
+}
+
+/// Sent right before the server drops the connection.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Kicked {
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AuthResponse {
+    pub name: String,
+    pub error: bool,
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+    /// Empty for guests
+    pub username: String,
+    /// Used instead of the RID or password on the next authentication
+    pub token: String,
+    /// Unix time in seconds
+    pub token_expires_at: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct CredentialsRegistered {
+    pub username: String,
+    /// The username is taken
+    pub error: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct TokenRefreshed {
+    pub token: String,
+    pub token_expires_at: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct TokensRevoked {
+    pub count: i32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Opponent {
+    pub name: String,
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+}
+
+/// Sent to every player of a new match. The top level name and picture are
+/// the ones of the first opponent.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct MatchFound {
+    pub name: String,
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+    pub opponents: Vec<Opponent>,
+    pub rounds: i32,
+    pub countdown_secs: i32,
+    /// Question, reveal and intermission together, for clients that time
+    /// the rounds themselves
+    pub stage_secs: i32,
+    pub question_secs: i32,
+    pub reveal_secs: i32,
+    pub intermission_secs: i32,
+}
+
+/// The match moved to another phase. Sent on every transition and when
+/// resuming a match.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PhaseChanged {
+    /// A `Phase`
+    pub phase: i32,
+    pub round: i32,
+    pub rounds: i32,
+    /// Unix time in milliseconds the phase ends at, 0 once finished
+    pub deadline_ms: i64,
+    /// Unix time in milliseconds of the server when sending, to correct for
+    /// the client's clock
+    pub server_time_ms: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuestionUpdate {
+    pub points: i32,
+    pub question: String,
+    /// Best score among the opponents
+    pub enemy_points: i32,
+    pub answer_option1: String,
+    pub answer_option2: String,
+    pub answer_option3: String,
+    pub answer_option4: String,
+    pub category: String,
+    pub difficulty: i32,
+    pub scoreboard: Vec<ScoreEntry>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScoreEntry {
+    pub name: String,
+    pub points: i32,
+    /// Starts at 1, players with the same points share a placement
+    pub placement: i32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Answer {
+    pub answer_index: i32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LeaderboardEntry {
+    pub win: i32,
+    pub lose: i32,
+    pub draw: i32,
+    pub name: String,
+    pub rating: i32,
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Leaderboard {
+    pub leaderboard: Vec<LeaderboardEntry>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProfilePicture {
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct NameUpdated {
+    pub name: String,
+    pub error: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct GameEnded {
+    /// A `GameOutcome`
+    pub outcome: i32,
+    /// Empty on a draw, "-" for legacy clients
+    pub winner: String,
+    /// A `GameEndReason`
+    pub reason: i32,
+    /// The receiving player's rating after the match
+    pub rating: i32,
+    /// 0 outside competitive matches
+    pub rating_delta: i32,
+    /// The receiving player's final placement
+    pub placement: i32,
+    pub scoreboard: Vec<ScoreEntry>,
+}
+
+/// An entry of both the friend list and the friend request list.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct FriendEntry {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    pub name: String,
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Friends {
+    pub friends: Vec<FriendEntry>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct FriendRequests {
+    pub friend_requests: Vec<FriendEntry>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SearchResult {
+    pub name: String,
+    pub found: bool,
+    #[serde(with = "serde_bytes")]
+    pub profile_picture: Vec<u8>,
+    pub profile_picture_extension: String,
+}
+
+/// Every packet the server sends. The payload is serialized untagged, the
+/// `PacketID` is prepended by `encode`.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Response {
+    Hello(HelloResponse),
+    Error(ErrorResponse),
+    Kicked(Kicked),
+    OpponentStatus(OpponentStatus),
+    QueueStatus(QueueStatus),
+    LobbyCreated(LobbyCreated),
+    LobbyUpdated(LobbyUpdated),
+    LobbyClosed(LobbyClosed),
+    ChallengeReceived(ChallengeReceived),
+    Authentication(AuthResponse),
+    MatchFound(MatchFound),
+    QuestionUpdate(QuestionUpdate),
+    PhaseChanged(PhaseChanged),
+    Answer(Answer),
+    Leaderboard(Leaderboard),
+    ProfilePictureChanged(ProfilePicture),
+    NameUpdated(NameUpdated),
+    GameEnded(GameEnded),
+    Friends(Friends),
+    FriendRequests(FriendRequests),
+    SearchName(SearchResult),
+    CredentialsRegistered(CredentialsRegistered),
+    TokenRefreshed(TokenRefreshed),
+    TokensRevoked(TokensRevoked),
+}
+
+impl Response {
+    pub fn packet_id(&self) -> u32 {
+        match self {
+            Self::Hello(_) => PACKET_ID_HELLO,
+            Self::Error(_) => PACKET_ID_ERROR,
+            Self::Kicked(_) => PACKET_ID_KICKED,
+            Self::OpponentStatus(_) => PACKET_ID_OPPONENT_STATUS,
+            Self::QueueStatus(_) => PACKET_ID_QUEUE_STATUS,
+            Self::LobbyCreated(_) => PACKET_ID_CREATE_LOBBY,
+            Self::LobbyUpdated(_) => PACKET_ID_JOIN_LOBBY,
+            Self::LobbyClosed(_) => PACKET_ID_LEAVE_LOBBY,
+            Self::ChallengeReceived(_) => PACKET_ID_CHALLENGE_FRIEND,
+            Self::Authentication(_) => PACKET_ID_AUTHENTICATION,
+            Self::MatchFound(_) => PACKET_ID_ADD_QUEUE,
+            Self::QuestionUpdate(_) => PACKET_ID_UPDATE_QUESTION,
+            Self::PhaseChanged(_) => PACKET_ID_PHASE_CHANGED,
+            Self::Answer(_) => PACKET_ID_QUESTION_ANSWER,
+            Self::Leaderboard(_) => PACKET_ID_FETCH_LEADERBOARD,
+            Self::ProfilePictureChanged(_) => PACKET_ID_CHANGE_PROFILE_PICTURE,
+            Self::NameUpdated(_) => PACKET_ID_UPDATE_NAME,
+            Self::GameEnded(_) => PACKET_ID_GAME_ENDED,
+            Self::Friends(_) => PACKET_ID_FETCH_FRIENDS,
+            Self::FriendRequests(_) => PACKET_ID_FETCH_FRIEND_REQUESTS,
+            Self::SearchName(_) => PACKET_ID_SEARCH_NAME,
+            Self::CredentialsRegistered(_) => PACKET_ID_REGISTER_CREDENTIALS,
+            Self::TokenRefreshed(_) => PACKET_ID_REFRESH_TOKEN,
+            Self::TokensRevoked(_) => PACKET_ID_REVOKE_TOKENS,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        let payload = bson::to_document(self).map_err(ProtocolError::Encode)?;
+
+        let mut document = doc! { "PacketID": self.packet_id() };
+        document.extend(payload);
+
+        bson::to_vec(&document).map_err(ProtocolError::Encode)
+    }
+}
@@ -0,0 +1,217 @@
+use crate::state::GameQuestion;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+pub const MIN_DIFFICULTY: i32 = 1;
+pub const MAX_DIFFICULTY: i32 = 3;
+
+/// How the difficulty of the questions develops over a match.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DifficultyCurve {
+    /// Any difficulty in every round
+    #[default]
+    Mixed,
+    /// Easy questions first, the hardest ones in the last round
+    Rising,
+}
+
+/// The questions a match asks for. A missing category or language matches
+/// every question.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct QuestionFilter {
+    pub category: Option<String>,
+    pub language: Option<String>,
+    pub curve: DifficultyCurve,
+}
+
+impl QuestionFilter {
+    /// Tags are compared in lowercase, empty ones are ignored.
+    pub fn new(category: Option<String>, language: Option<String>, curve: DifficultyCurve) -> Self {
+        let normalize =
+            |x: Option<String>| x.map(|x| x.trim().to_lowercase()).filter(|x| !x.is_empty());
+
+        Self {
+            category: normalize(category),
+            language: normalize(language),
+            curve,
+        }
+    }
+
+    pub fn matches(&self, question: &GameQuestion) -> bool {
+        let category = self
+            .category
+            .as_ref()
+            .is_none_or(|x| x.eq_ignore_ascii_case(&question.category));
+        let language = self
+            .language
+            .as_ref()
+            .is_none_or(|x| x.eq_ignore_ascii_case(&question.language));
+
+        category && language
+    }
+
+    /// The difficulty wanted in `round` (starting at 1) of `rounds`, if the
+    /// curve asks for one.
+    fn target_difficulty(&self, round: i32, rounds: i32) -> Option<i32> {
+        match self.curve {
+            DifficultyCurve::Mixed => None,
+            DifficultyCurve::Rising => {
+                let progress = (round - 1).max(0) as f64 / (rounds - 1).max(1) as f64;
+                let steps = (MAX_DIFFICULTY - MIN_DIFFICULTY) as f64;
+
+                Some(MIN_DIFFICULTY + (progress.min(1.0) * steps).round() as i32)
+            }
+        }
+    }
+}
+
+/// Picks the question of `round` and returns its index in `questions`.
+/// Questions whose ID is in `previous` are only asked again once every
+/// matching one was, and the filter is ignored if nothing matches it. The
+/// curve prefers the questions closest to its difficulty.
+pub fn pick(
+    questions: &[GameQuestion],
+    filter: &QuestionFilter,
+    round: i32,
+    rounds: i32,
+    previous: &HashSet<i32>,
+) -> Option<usize> {
+    let mut candidates = (0..questions.len())
+        .filter(|&i| filter.matches(&questions[i]))
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        candidates = (0..questions.len()).collect();
+    }
+
+    let unseen = |i: &usize| !previous.contains(&questions[*i].id);
+    if candidates.iter().any(unseen) {
+        candidates.retain(unseen);
+    }
+
+    if let Some(target) = filter.target_difficulty(round, rounds) {
+        let distance = |i: &usize| (questions[*i].difficulty - target).abs();
+        let closest = candidates.iter().map(distance).min()?;
+        candidates.retain(|i| distance(i) == closest);
+    }
+
+    candidates.choose(&mut rand::thread_rng()).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(id: i32, category: &str, difficulty: i32, language: &str) -> GameQuestion {
+        GameQuestion {
+            id,
+            question: format!("Question {}", id),
+            answer_option_1: "A".to_string(),
+            answer_option_2: "B".to_string(),
+            answer_option_3: "C".to_string(),
+            answer_option_4: "D".to_string(),
+            answer_index: 0,
+            category: category.to_string(),
+            difficulty,
+            language: language.to_string(),
+        }
+    }
+
+    fn filter(
+        category: Option<&str>,
+        language: Option<&str>,
+        curve: DifficultyCurve,
+    ) -> QuestionFilter {
+        QuestionFilter::new(
+            category.map(str::to_string),
+            language.map(str::to_string),
+            curve,
+        )
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        let filter = filter(Some(" Science "), Some(""), DifficultyCurve::Mixed);
+
+        assert_eq!(filter.category.as_deref(), Some("science"));
+        assert_eq!(filter.language, None);
+        assert!(filter.matches(&question(1, "SCIENCE", 2, "en")));
+        assert!(!filter.matches(&question(2, "history", 2, "en")));
+    }
+
+    #[test]
+    fn category_and_language_both_have_to_match() {
+        let filter = filter(Some("science"), Some("en"), DifficultyCurve::Mixed);
+
+        assert!(filter.matches(&question(1, "science", 2, "en")));
+        assert!(!filter.matches(&question(2, "science", 2, "id")));
+        assert!(!filter.matches(&question(3, "history", 2, "en")));
+    }
+
+    #[test]
+    fn rising_curve_goes_from_easy_to_hard() {
+        let rising = filter(None, None, DifficultyCurve::Rising);
+        let targets = (1..=5)
+            .map(|round| rising.target_difficulty(round, 5))
+            .collect::<Vec<_>>();
+        assert_eq!(targets, [Some(1), Some(2), Some(2), Some(3), Some(3)]);
+
+        // A single round is the first and the last one
+        assert_eq!(rising.target_difficulty(1, 1), Some(MIN_DIFFICULTY));
+
+        let mixed = filter(None, None, DifficultyCurve::Mixed);
+        assert_eq!(mixed.target_difficulty(3, 5), None);
+    }
+
+    #[test]
+    fn pick_follows_the_filter_and_the_curve() {
+        let questions = [
+            question(1, "science", 1, "id"),
+            question(2, "science", 3, "id"),
+            question(3, "history", 1, "id"),
+            question(4, "history", 3, "id"),
+        ];
+        let rising = filter(Some("history"), None, DifficultyCurve::Rising);
+
+        for _ in 0..20 {
+            let first = pick(&questions, &rising, 1, 3, &HashSet::new()).unwrap();
+            assert_eq!(questions[first].id, 3);
+
+            let last = pick(&questions, &rising, 3, 3, &HashSet::new()).unwrap();
+            assert_eq!(questions[last].id, 4);
+        }
+    }
+
+    #[test]
+    fn questions_are_not_repeated_until_every_one_was_asked() {
+        let questions = [
+            question(1, "science", 2, "id"),
+            question(2, "science", 2, "id"),
+            question(3, "history", 2, "id"),
+        ];
+        let science = filter(Some("science"), None, DifficultyCurve::Mixed);
+
+        for _ in 0..20 {
+            let index = pick(&questions, &science, 2, 3, &HashSet::from([1])).unwrap();
+            assert_eq!(questions[index].id, 2);
+        }
+
+        // Both were asked, so either may come again, but still no history
+        let previous = HashSet::from([1, 2]);
+        for _ in 0..20 {
+            let index = pick(&questions, &science, 3, 3, &previous).unwrap();
+            assert_ne!(questions[index].id, 3);
+        }
+    }
+
+    #[test]
+    fn unmatched_filter_falls_back_to_every_question() {
+        let questions = [question(1, "science", 2, "id")];
+        let history = filter(Some("history"), None, DifficultyCurve::Mixed);
+
+        assert_eq!(pick(&questions, &history, 1, 1, &HashSet::new()), Some(0));
+        assert_eq!(pick(&[], &history, 1, 1, &HashSet::new()), None);
+    }
+}
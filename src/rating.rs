@@ -0,0 +1,196 @@
+use std::f64::consts::PI;
+
+/// Converts between the displayed rating and the Glicko-2 scale.
+const SCALE: f64 = 173.7178;
+/// Constrains how fast the volatility changes, 0.3 to 1.2 is reasonable
+const TAU: f64 = 0.5;
+const CONVERGENCE_TOLERANCE: f64 = 0.000001;
+
+/// Deviation of a player who never played, also the upper bound.
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+
+/// A player's Glicko-2 rating. Only rating differences matter, so ratings
+/// keep starting at 0 instead of the usual 1500.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glicko {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+impl Glicko {
+    /// The rating after one match. Every match is its own rating period, a
+    /// free-for-all counts as a game against each opponent. `results` holds
+    /// every opponent with the score against them, 1 for a win, 0.5 for a
+    /// draw and 0 for a loss.
+    pub fn rate(self, results: &[(Glicko, f64)]) -> Glicko {
+        let mu = self.rating / SCALE;
+        let phi = self.deviation / SCALE;
+
+        if results.is_empty() {
+            let deviation = (phi * phi + self.volatility * self.volatility).sqrt() * SCALE;
+            return Glicko {
+                deviation: deviation.min(DEFAULT_DEVIATION),
+                ..self
+            };
+        }
+
+        let mut inverse_variance = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in results {
+            let opponent_g = g(opponent.deviation / SCALE);
+            let expected = 1.0 / (1.0 + (-opponent_g * (mu - opponent.rating / SCALE)).exp());
+
+            inverse_variance += opponent_g * opponent_g * expected * (1.0 - expected);
+            improvement += opponent_g * (score - expected);
+        }
+
+        let variance = 1.0 / inverse_variance;
+        let delta = variance * improvement;
+
+        let volatility = self.new_volatility(phi, variance, delta);
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / variance).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+
+        Glicko {
+            rating: new_mu * SCALE,
+            deviation: (new_phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+
+    /// Step 5 of the Glicko-2 paper, solved with the Illinois algorithm.
+    fn new_volatility(self, phi: f64, variance: f64, delta: f64) -> f64 {
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + variance + ex;
+            ex * (delta * delta - phi * phi - variance - ex) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+        };
+
+        let mut lower = a;
+        let mut upper = if delta * delta > phi * phi + variance {
+            (delta * delta - phi * phi - variance).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+
+            a - k * TAU
+        };
+
+        let mut f_lower = f(lower);
+        let mut f_upper = f(upper);
+        while (upper - lower).abs() > CONVERGENCE_TOLERANCE {
+            let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_c = f(c);
+
+            if f_c * f_upper <= 0.0 {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.0;
+            }
+
+            upper = c;
+            f_upper = f_c;
+        }
+
+        (lower / 2.0).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::GameOutcome;
+
+    fn glicko(rating: f64, deviation: f64) -> Glicko {
+        Glicko {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not {} ± {}",
+            actual,
+            expected,
+            tolerance
+        );
+    }
+
+    /// The example of the Glicko-2 paper, shifted by the usual 1500.
+    #[test]
+    fn paper_example() {
+        let player = glicko(0.0, 200.0);
+        let results = [
+            (glicko(-100.0, 30.0), 1.0),
+            (glicko(50.0, 100.0), 0.0),
+            (glicko(200.0, 300.0), 0.0),
+        ];
+
+        let rated = player.rate(&results);
+        assert_close(rated.rating, -35.95, 0.01);
+        assert_close(rated.deviation, 151.52, 0.01);
+        assert_close(rated.volatility, 0.05999, 0.00001);
+    }
+
+    #[test]
+    fn draw_between_equals_keeps_the_rating() {
+        let player = glicko(100.0, 120.0);
+
+        let rated = player.rate(&[(player, 0.5)]);
+        assert_close(rated.rating, 100.0, 0.000001);
+        assert!(rated.deviation < player.deviation);
+
+        // Winning and losing move the rating by the same amount
+        let won = player.rate(&[(player, 1.0)]);
+        let lost = player.rate(&[(player, 0.0)]);
+        assert!(won.rating > 100.0);
+        assert_close(won.rating - 100.0, 100.0 - lost.rating, 0.000001);
+    }
+
+    #[test]
+    fn draw_moves_towards_the_opponent() {
+        let weaker = glicko(-200.0, 80.0);
+        let stronger = glicko(200.0, 80.0);
+        let draw = GameOutcome::Draw.score();
+
+        let rated = weaker.rate(&[(stronger, draw)]);
+        assert!(rated.rating > weaker.rating);
+        assert!(rated.rating < weaker.rate(&[(stronger, GameOutcome::Win.score())]).rating);
+
+        let rated = stronger.rate(&[(weaker, draw)]);
+        assert!(rated.rating < stronger.rating);
+        assert!(rated.rating > stronger.rate(&[(weaker, GameOutcome::Loss.score())]).rating);
+    }
+
+    #[test]
+    fn no_results_only_grows_the_deviation() {
+        let player = glicko(250.0, 100.0);
+
+        let rated = player.rate(&[]);
+        assert_eq!(rated.rating, 250.0);
+        assert_eq!(rated.volatility, DEFAULT_VOLATILITY);
+
+        let phi = 100.0 / SCALE;
+        let expected = (phi * phi + DEFAULT_VOLATILITY * DEFAULT_VOLATILITY).sqrt() * SCALE;
+        assert_close(rated.deviation, expected, 0.000001);
+
+        // Never past the deviation of a new player
+        let rated = glicko(0.0, DEFAULT_DEVIATION).rate(&[]);
+        assert_eq!(rated.deviation, DEFAULT_DEVIATION);
+    }
+}
@@ -1,55 +1,91 @@
-use crate::{database::Database, game::Game, player::Player};
+use crate::{
+    config::{Config, MatchRules},
+    database::{self, pool::DatabasePool},
+    game::{Game, GameId},
+    lobby::{Lobbies, Lobby},
+    matchmaking::Matchmaker,
+    player::{Player, SessionState},
+    question::QuestionFilter,
+};
 use enet::PeerID;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Instant};
 
 type PlayersMap = HashMap<PeerID, Rc<RefCell<Player>>>;
 pub type PacketSent = (PeerID, Vec<u8>);
 
 #[derive(Default, Debug, Clone)]
 pub struct GameQuestion {
+    /// Stays the same across reloads of the question bank
+    pub id: i32,
     pub question: String,
     pub answer_option_1: String,
     pub answer_option_2: String,
     pub answer_option_3: String,
     pub answer_option_4: String,
     pub answer_index: i32,
+    pub category: String,
+    /// From `question::MIN_DIFFICULTY` (easy) to `question::MAX_DIFFICULTY`
+    pub difficulty: i32,
+    /// Language code like "id" or "en"
+    pub language: String,
 }
 
 pub struct State {
-    pub games: Vec<Game>,
+    pub games: HashMap<GameId, Game>,
+    next_game_id: GameId,
     pub players: PlayersMap,
+    /// Serial of the next connection, see `Player::serial`
+    next_serial: u64,
     pub packets: Rc<RefCell<Vec<PacketSent>>>,
-    pub database: Database,
+    /// Peers to disconnect once their queued packets are sent
+    pub pending_disconnects: Vec<PeerID>,
+    pub database: DatabasePool,
     pub questions: Vec<GameQuestion>,
-    pub last_queue: [Option<PeerID>; 2],
-}
-
-impl Default for State {
-    fn default() -> Self {
-        Self::new()
-    }
+    pub questions_loaded_at: Instant,
+    pub matchmaker: Matchmaker,
+    pub lobbies: Lobbies,
+    pub config: Config,
 }
 
 impl State {
-    pub fn new() -> Self {
-        let mut database = Database::new();
-        let questions = database.get_all_questions().unwrap();
+    pub fn new(config: Config) -> Self {
+        let mut storages =
+            database::open(&config).unwrap_or_else(|e| panic!("Cannot open database: {:#}", e));
+        let questions = storages[0].get_all_questions().unwrap();
+        if questions.is_empty() {
+            panic!("The question bank is empty, import questions first");
+        }
+
+        let database = DatabasePool::new(storages);
 
         Self {
+            config,
             database,
             questions,
+            questions_loaded_at: Instant::now(),
 
-            games: Vec::new(),
+            games: HashMap::new(),
+            next_game_id: 1,
+            next_serial: 1,
             players: HashMap::new(),
             packets: Rc::new(RefCell::new(Vec::new())),
-            last_queue: [None, None],
+            pending_disconnects: Vec::new(),
+            matchmaker: Matchmaker::default(),
+            lobbies: Lobbies::default(),
         }
     }
 
     pub fn add_player(&mut self, peer_id: PeerID) {
+        let serial = self.next_serial;
+        self.next_serial += 1;
+
         self.players.insert(
             peer_id,
-            Rc::new(RefCell::new(Player::new(peer_id, self.packets.clone()))),
+            Rc::new(RefCell::new(Player::new(
+                peer_id,
+                serial,
+                self.packets.clone(),
+            ))),
         );
     }
 
@@ -57,31 +93,120 @@ impl State {
         self.players.get(&peer_id).cloned()
     }
 
-    pub fn remove_player(&mut self, peer_id: PeerID) {
-        self.players.remove(&peer_id);
+    /// An authenticated player by account ID. Players borrowed by the caller
+    /// are skipped.
+    pub fn get_player_by_id(&self, id: i32) -> Option<Rc<RefCell<Player>>> {
+        self.players
+            .values()
+            .find(|x| {
+                x.try_borrow()
+                    .is_ok_and(|x| x.id == id && x.session.is_authenticated())
+            })
+            .cloned()
+    }
 
-        for last_queue in self.last_queue.iter_mut() {
-            if let Some(queue) = last_queue.as_ref() {
-                if *queue == peer_id {
-                    *last_queue = None;
-                }
+    /// Takes `player` out of its lobby. A leaving host closes the lobby and
+    /// tells the guests and a challenged friend, a leaving guest is removed
+    /// from the member list everyone else sees.
+    pub fn leave_lobby(&mut self, player: &Player) -> Option<Lobby> {
+        let Some(lobby) = self.lobbies.remove_by_host(player.peer_id) else {
+            let lobby = self.lobbies.remove_guest(player.peer_id)?.clone();
+            self.send_lobby_updated(&lobby, player);
+            return Some(lobby);
+        };
+
+        if let Some(invited) = lobby.invited.and_then(|id| self.get_player_by_id(id)) {
+            invited
+                .borrow()
+                .send_lobby_closed(&lobby.code, "Challenge withdrawn");
+        }
+
+        for (peer_id, _) in lobby.guests.iter() {
+            if let Some(guest) = self.get_player(*peer_id) {
+                let mut guest = guest.borrow_mut();
+                guest.session = SessionState::Authenticated;
+                guest.send_lobby_closed(&lobby.code, "Host left the lobby");
             }
         }
 
-        self.games.retain(|game| {
-            let p1 = game.p1.borrow();
-            let p2 = game.p2.borrow();
-            p1.peer_id != peer_id && p2.peer_id != peer_id
-        });
+        Some(lobby)
+    }
+
+    /// Sends the members of `lobby` to all of them. `player` is the one
+    /// borrowed by the caller.
+    pub fn send_lobby_updated(&self, lobby: &Lobby, player: &Player) {
+        let others = lobby
+            .members()
+            .filter(|x| *x != player.peer_id)
+            .filter_map(|x| self.get_player(x))
+            .collect::<Vec<_>>();
+
+        let mut names = others
+            .iter()
+            .map(|x| x.borrow().name.clone())
+            .collect::<Vec<_>>();
+
+        // Host first, like the lobby itself
+        if let Some(index) = lobby.members().position(|x| x == player.peer_id) {
+            names.insert(index.min(names.len()), player.name.clone());
+            player.send_lobby_updated(&lobby.code, &names, lobby.capacity);
+        }
+
+        for other in others {
+            other
+                .borrow()
+                .send_lobby_updated(&lobby.code, &names, lobby.capacity);
+        }
+    }
+
+    /// Unlike `Peer::disconnect_later`, lets the packets queued for the peer
+    /// in this tick reach it first.
+    pub fn disconnect_after_send(&mut self, peer_id: PeerID) {
+        self.pending_disconnects.push(peer_id);
+    }
+
+    /// A player in a match keeps its slot in the game, so it can reconnect
+    /// within `reconnect_grace_secs`. See `take_over_session`.
+    pub fn remove_player(&mut self, peer_id: PeerID) {
+        let Some(player) = self.players.remove(&peer_id) else {
+            return;
+        };
+
+        let mut player_ref = player.borrow_mut();
+        self.matchmaker.leave(peer_id);
+        self.leave_lobby(&player_ref);
+
+        if player_ref.session != SessionState::InGame {
+            return;
+        }
+
+        player_ref.disconnected_at = Some(Instant::now());
+
+        if let Some(game) = player_ref.game_id.and_then(|id| self.games.get(&id)) {
+            for opponent in game.opponents(&player) {
+                opponent.borrow().send_opponent_status(
+                    &player_ref.name,
+                    false,
+                    self.config.game.reconnect_grace_secs,
+                );
+            }
+        }
     }
 
     pub fn add_game(
         &mut self,
-        p1: Rc<RefCell<Player>>,
-        p2: Rc<RefCell<Player>>,
+        players: Vec<Rc<RefCell<Player>>>,
         competitive: bool,
-    ) -> usize {
-        self.games.push(Game::new(p1, p2, competitive));
-        self.games.len() - 1
+        rules: MatchRules,
+        question_filter: QuestionFilter,
+    ) -> GameId {
+        let id = self.next_game_id;
+        self.next_game_id += 1;
+
+        self.games.insert(
+            id,
+            Game::new(id, players, competitive, rules, question_filter),
+        );
+        id
     }
 }